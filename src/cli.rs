@@ -0,0 +1,262 @@
+use clap::{Parser, Subcommand};
+use std::time::Instant;
+
+use crate::db::{Account, Database, SessionSettings};
+use crate::protocol::session::{format_trace, mermaid_diagram, PromptMatcher, PromptReplies, Session};
+use crate::protocol::socket::{EvertextClient, RunMode};
+
+#[derive(Parser)]
+#[command(name = "evertext_bot_rust", about = "EverText automation bot")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Start the Discord bot (default when no subcommand is given)
+    Run {
+        /// Path to the database file (overrides DATABASE_PATH)
+        #[arg(long)]
+        config: Option<String>,
+        /// Run a single session for this account in the terminal instead of starting the bot
+        #[arg(long)]
+        account: Option<String>,
+        /// Run mode for --account (daily, handout, or a sequence such as handout,daily; default daily)
+        #[arg(long, requires = "account")]
+        mode: Option<RunMode>,
+        /// With --account, also print the commands sent as a script with the final screen
+        #[arg(long, requires = "account")]
+        script: bool,
+    },
+    /// Check an account's stored data and test a handshake with the game server
+    Diagnose {
+        /// Account name as registered in the database
+        #[arg(long)]
+        account: String,
+    },
+    /// Feed a recorded transcript (see TRANSCRIPT_DIR) through the prompt handler
+    Replay {
+        /// Transcript file with one raw socket frame per line
+        #[arg(long)]
+        file: String,
+        /// Run mode to replay with (daily or handout)
+        #[arg(long, default_value = "daily")]
+        mode: RunMode,
+        /// Use this account's settings (target server, mana responses)
+        #[arg(long)]
+        account: Option<String>,
+        /// Also print the commands it would send as a script with the final screen
+        #[arg(long)]
+        script: bool,
+    },
+    /// Print the prompt rules and game state flow as a Mermaid diagram
+    Diagram {
+        /// Include the prompts configured in this database file's session settings
+        #[arg(long)]
+        config: Option<String>,
+    },
+}
+
+fn find_account(db: &Database, name: &str) -> Result<Account, Box<dyn std::error::Error + Send + Sync>> {
+    db.data.accounts.iter()
+        .find(|a| a.name == name)
+        .cloned()
+        .ok_or_else(|| format!("Account '{}' not found", name).into())
+}
+
+pub async fn diagnose(name: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let db = Database::load()?;
+    let acc = find_account(&db, name)?;
+
+    println!("[DIAG] Account: {}", acc.name);
+    println!("[DIAG] Owner: {} ({})", acc.username.as_deref().unwrap_or("unknown"), acc.user_id.as_deref().unwrap_or("-"));
+    println!("[DIAG] Target server: {}", acc.target_server.as_deref().unwrap_or("Default"));
+    println!("[DIAG] Status: {} (last run: {})", acc.status, acc.last_run.as_deref().unwrap_or("never"));
+    println!("[DIAG] Handout enabled: {}", acc.handout_enabled);
+
+    let code = acc.decrypt_code();
+    let key_state = if std::env::var("ENCRYPTION_KEY").is_ok() { "set" } else { "not set" };
+    if code.is_empty() {
+        println!("[DIAG] Restore code: MISSING");
+    } else if db.data.settings.session.is_placeholder_code(&code) {
+        println!("[DIAG] Restore code: looks like a PLACEHOLDER ({} chars, ENCRYPTION_KEY {})", code.len(), key_state);
+    } else {
+        println!("[DIAG] Restore code: {} chars (ENCRYPTION_KEY {})", code.len(), key_state);
+    }
+
+    let cookie = db.data.settings.cookies.clone().unwrap_or_default();
+    if let Some(command) = &acc.cookie_command {
+        println!("[DIAG] Cookie command: {}", command);
+    }
+    if !acc.has_cookie_source(&cookie) {
+        println!("[DIAG] Session cookie: NOT SET, skipping connection test.");
+        return Ok(());
+    }
+
+    let started = Instant::now();
+    match EvertextClient::connect_account(&acc, &cookie, &db.data.settings.session).await {
+        Ok(_) => println!("[DIAG] Handshake OK in {} ms", started.elapsed().as_millis()),
+        Err(e) => println!("[DIAG] Handshake FAILED after {} ms: {}", started.elapsed().as_millis(), e),
+    }
+    Ok(())
+}
+
+/// Runs one session for `name` without Discord, retrying connection issues
+/// like the queue does. Prints the decision trace when the session ends.
+pub async fn run_account(name: &str, mode: RunMode, script: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    const MAX_RECONNECTS: usize = 3;
+    let db = Database::load()?;
+    let acc = find_account(&db, name)?;
+    let cookie = db.data.settings.cookies.clone().unwrap_or_default();
+    if !acc.has_cookie_source(&cookie) {
+        return Err("Session cookie is not set".into());
+    }
+    let settings = db.data.settings.session.clone();
+    let code = acc.decrypt_code();
+    if settings.is_placeholder_code(&code) {
+        return Err(format!("PLACEHOLDER_CODE: {}'s restore code looks like a placeholder", acc.name).into());
+    }
+
+    let mut resume = None;
+    for attempt in 0..=MAX_RECONNECTS {
+        let mut client = match EvertextClient::connect_account(&acc, &cookie, &settings).await {
+            Ok(client) => client,
+            Err(e) if attempt < MAX_RECONNECTS => {
+                println!("[RUN] Connection failed ({}). Retrying in 5s ({}/{})...", e, attempt + 1, MAX_RECONNECTS);
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                continue;
+            },
+            Err(e) => return Err(e),
+        };
+        if let Some(point) = resume.take() {
+            client.resume_from(point);
+        }
+        if let Some(policy) = crate::loop_window_policy(&acc) {
+            client.set_continue_policy(policy);
+        }
+        let result = client.run_loop(&acc, &code, mode.clone()).await;
+
+        println!("[RUN] Decision trace for {} (run {}, sid {}):", acc.name, client.run_id(), client.sid());
+        for line in format_trace(&client.trace()) {
+            println!("  {}", line);
+        }
+        println!("[RUN] Final screen:");
+        for line in client.final_screen() {
+            println!("  | {}", line);
+        }
+        if let Some(balance) = client.balance() {
+            println!("[RUN] Last balance: {}", balance);
+        }
+        let rewards = client.rewards();
+        if !rewards.is_empty() {
+            println!("[RUN] Gained: {}", rewards.describe());
+        }
+        if script {
+            println!("[RUN] Command script:");
+            print!("{}", client.command_script());
+        }
+        let err_str = match result {
+            Ok(_) => return Ok(()),
+            Err(e) => e.to_string(),
+        };
+        if err_str.contains("SESSION_COMPLETE") {
+            println!("[RUN] {} completed.", acc.name);
+            return Ok(());
+        }
+        if err_str.contains("EVENT_UNAVAILABLE") {
+            println!("[RUN] Event not available for {}.", acc.name);
+            return Ok(());
+        }
+        if err_str.contains("CODE_ALREADY_USED") {
+            println!("[RUN] {}'s restore code was already used today.", acc.name);
+            return Ok(());
+        }
+        if !crate::is_connection_issue(&err_str) || attempt == MAX_RECONNECTS {
+            return Err(err_str.into());
+        }
+        println!("[RUN] Connection issue ({}). Reconnecting in 5s ({}/{})...", err_str, attempt + 1, MAX_RECONNECTS);
+        if settings.resume_on_reconnect {
+            resume = client.resume_point();
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    }
+    Ok(())
+}
+
+pub fn replay(file: &str, mode: RunMode, account: Option<&str>, script: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let content = std::fs::read_to_string(file)?;
+    let (acc, settings) = match account {
+        Some(name) => {
+            let db = Database::load()?;
+            (find_account(&db, name)?, db.data.settings.session.clone())
+        },
+        None => (Account { name: "replay".to_string(), ..Default::default() }, SessionSettings::default()),
+    };
+    let code = if acc.code.is_empty() { "<restore code>".to_string() } else { acc.decrypt_code() };
+
+    let mut session = Session::new(&acc, &code, mode, &settings)?;
+    session.set_prompt_matcher(PromptReplies::from_settings(&settings).map(|m| Box::new(m) as Box<dyn PromptMatcher + Send + Sync>));
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim_end();
+        if let Some(sent) = line.strip_prefix(">> ") {
+            println!("[REPLAY] line {}: originally sent {}", n + 1, sent);
+            continue;
+        }
+        // Older transcripts have no direction marker and hold received frames only.
+        let frame = line.strip_prefix("<< ").unwrap_or(line);
+        if !frame.starts_with("42") {
+            continue;
+        }
+        let result = session.handle_event(frame);
+        for cmd in session.take_commands() {
+            match settings.macros.get(&cmd) {
+                Some(steps) => println!("[REPLAY] line {}: would send '{}' (macro: {})", n + 1, cmd, steps.join(", ")),
+                None => println!("[REPLAY] line {}: would send '{}'", n + 1, cmd),
+            }
+        }
+        if let Err(e) = result {
+            println!("[REPLAY] line {}: session ended with {}", n + 1, e);
+            print_trace(&session, script);
+            return Ok(());
+        }
+    }
+    println!("[REPLAY] Transcript ended without a terminal outcome.");
+    print_trace(&session, script);
+    Ok(())
+}
+
+pub fn diagram(config: Option<&str>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let settings = match config {
+        Some(path) => {
+            std::env::set_var("DATABASE_PATH", path);
+            Database::load()?.data.settings.session
+        },
+        None => SessionSettings::default(),
+    };
+    print!("{}", mermaid_diagram(&settings));
+    Ok(())
+}
+
+fn print_trace(session: &Session, script: bool) {
+    println!("[REPLAY] Decision trace:");
+    for line in format_trace(&session.trace()) {
+        println!("  {}", line);
+    }
+    println!("[REPLAY] Final screen:");
+    for line in session.final_screen() {
+        println!("  | {}", line);
+    }
+    let rewards = session.rewards();
+    if !rewards.is_empty() {
+        println!("[REPLAY] Gained: {}", rewards.describe());
+    }
+    let unhandled = session.unhandled_events();
+    if !unhandled.is_empty() {
+        println!("[REPLAY] Unhandled events: {}", unhandled.join(", "));
+    }
+    if script {
+        println!("[REPLAY] Command script:");
+        print!("{}", session.command_script());
+    }
+}
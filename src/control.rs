@@ -0,0 +1,167 @@
+use serde_json::json;
+use serenity::all::Context;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::broadcast;
+use warp::http::StatusCode;
+use warp::Filter;
+
+use crate::Handler;
+
+/// Starts the HTTP control API when CONTROL_API_TOKEN is set. Every request needs
+/// `Authorization: Bearer <token>`; it listens on CONTROL_API_ADDR (default 127.0.0.1:8081).
+///
+/// - `GET /status`: queue state and every account's status
+/// - `POST /run[?group=name]`: start the daily queue (409 while one runs, 503 while shutting down)
+/// - `POST /abort/<account>`: end one running session
+/// - `POST /pause`, `POST /resume`: flip the pause flag
+/// - `GET /output`: every account's game output as server-sent events, "<account>: <line>"
+pub fn spawn(handler: Handler, ctx: Context) {
+    // `ready` fires again after a gateway reconnect; the port is only bound once.
+    static STARTED: AtomicBool = AtomicBool::new(false);
+    let token = std::env::var("CONTROL_API_TOKEN").unwrap_or_default();
+    if token.is_empty() || STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let raw_addr = std::env::var("CONTROL_API_ADDR").unwrap_or_else(|_| "127.0.0.1:8081".to_string());
+    let addr: SocketAddr = match raw_addr.parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            println!("[ERROR] Control API: CONTROL_API_ADDR '{}' is not an address ({}). Not starting it.", raw_addr, e);
+            return;
+        },
+    };
+
+    let expected = format!("Bearer {}", token);
+    let auth = warp::header::optional::<String>("authorization")
+        .map(move |header: Option<String>| header.is_some_and(|h| constant_time_eq(h.as_bytes(), expected.as_bytes())));
+    let state = warp::any().map(move || (handler.clone(), ctx.clone()));
+
+    let status = warp::get().and(warp::path("status")).and(warp::path::end())
+        .and(auth.clone()).and(state.clone())
+        .then(|ok: bool, (handler, _): (Handler, Context)| async move {
+            if !ok {
+                return unauthorized();
+            }
+            reply(StatusCode::OK, status_json(&handler).await)
+        });
+    let run = warp::post().and(warp::path("run")).and(warp::path::end())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth.clone()).and(state.clone())
+        .then(|query: std::collections::HashMap<String, String>, ok: bool, (handler, ctx): (Handler, Context)| async move {
+            if !ok {
+                return unauthorized();
+            }
+            let group = query.get("group").cloned().filter(|g| !g.is_empty());
+            if handler.process_queue(ctx, None, group.clone(), None).await {
+                println!("[INFO] Control API: started the queue (group: {}).", group.as_deref().unwrap_or("all"));
+                reply(StatusCode::ACCEPTED, json!({"started": true}))
+            } else if handler.draining.load(Ordering::Relaxed) {
+                reply(StatusCode::SERVICE_UNAVAILABLE, json!({"error": "shutting down"}))
+            } else {
+                reply(StatusCode::CONFLICT, json!({"error": "queue already running"}))
+            }
+        });
+    let abort = warp::post().and(warp::path!("abort" / String))
+        .and(auth.clone()).and(state.clone())
+        .then(|name: String, ok: bool, (handler, _): (Handler, Context)| async move {
+            if !ok {
+                return unauthorized();
+            }
+            let name = percent_decode(&name);
+            match handler.aborts.lock().await.get(&name) {
+                Some(flag) => {
+                    flag.store(true, Ordering::Relaxed);
+                    println!("[INFO] Control API: aborting {}.", name);
+                    reply(StatusCode::ACCEPTED, json!({"aborting": name}))
+                },
+                None => reply(StatusCode::NOT_FOUND, json!({"error": format!("{} is not running", name)})),
+            }
+        });
+    let output = warp::get().and(warp::path("output")).and(warp::path::end())
+        .and(auth.clone()).and(state.clone())
+        .map(|ok: bool, (handler, _): (Handler, Context)| -> Box<dyn warp::Reply> {
+            if !ok {
+                return Box::new(unauthorized());
+            }
+            let stream = futures_util::stream::unfold(handler.output.subscribe(), |mut rx| async move {
+                loop {
+                    match rx.recv().await {
+                        Ok(line) => return Some((Ok::<_, std::convert::Infallible>(warp::sse::Event::default().data(line)), rx)),
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            });
+            Box::new(warp::sse::reply(warp::sse::keep_alive().stream(stream)))
+        });
+    let pause = warp::post().and(warp::path!("pause"))
+        .and(auth.clone()).and(state.clone())
+        .then(|ok: bool, (handler, _): (Handler, Context)| async move { set_paused(ok, &handler, true) });
+    let resume = warp::post().and(warp::path!("resume"))
+        .and(auth).and(state)
+        .then(|ok: bool, (handler, _): (Handler, Context)| async move { set_paused(ok, &handler, false) });
+
+    tokio::spawn(async move {
+        println!("[INFO] Control API listening on {}", addr);
+        warp::serve(status.or(run).or(abort).or(output).or(pause).or(resume)).run(addr).await;
+    });
+}
+
+async fn status_json(handler: &Handler) -> serde_json::Value {
+    let processing = *handler.is_processing.lock().await;
+    let mut running: Vec<String> = handler.aborts.lock().await.keys().cloned().collect();
+    running.sort();
+    let db = handler.db.lock().await;
+    let accounts: Vec<_> = db.data.accounts.iter().map(|a| json!({
+        "name": a.name,
+        "status": a.status,
+        "lastRun": a.last_run,
+        "group": a.group,
+        "quarantined": a.is_quarantined(),
+        "running": running.contains(&a.name),
+    })).collect();
+    json!({
+        "processing": processing,
+        "paused": handler.paused.load(Ordering::Relaxed),
+        "running": running,
+        "accounts": accounts,
+    })
+}
+
+fn set_paused(ok: bool, handler: &Handler, paused: bool) -> warp::reply::WithStatus<warp::reply::Json> {
+    if !ok {
+        return unauthorized();
+    }
+    handler.paused.store(paused, Ordering::Relaxed);
+    println!("[INFO] Control API: automation {}.", if paused { "paused" } else { "resumed" });
+    reply(StatusCode::OK, json!({"paused": paused}))
+}
+
+fn reply(code: StatusCode, body: serde_json::Value) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(warp::reply::json(&body), code)
+}
+
+/// Compares the whole of both inputs, so the time taken doesn't tell how much of the token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn unauthorized() -> warp::reply::WithStatus<warp::reply::Json> {
+    reply(StatusCode::UNAUTHORIZED, json!({"error": "missing or wrong bearer token"}))
+}
+
+/// Decodes `%XX` escapes in a path segment, so account names may contain spaces.
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%').then(|| segment.get(i + 1..i + 3)).flatten().and_then(|h| u8::from_str_radix(h, 16).ok());
+        match hex {
+            Some(byte) => { out.push(byte); i += 3; },
+            None => { out.push(bytes[i]); i += 1; },
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -1,8 +1,10 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use chrono;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+use crate::protocol::socket::RunMode;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Account {
     pub name: String,
     pub code: String,
@@ -20,6 +22,69 @@ pub struct Account {
     pub status: String,
     #[serde(rename = "lastRun")]
     pub last_run: Option<String>,
+    // Scripted answers to the mana prompt, keyed by mode ("daily" / "handout").
+    // The last entry repeats once the list is exhausted.
+    #[serde(rename = "manaResponses", default)]
+    pub mana_responses: HashMap<String, Vec<String>>,
+    // "HH:MM-HH:MM" (Jakarta time). Inside this window the bot answers 'y' to
+    // "Press y to perform more commands" and keeps farming instead of finishing.
+    #[serde(rename = "loopWindow", default)]
+    pub loop_window: Option<String>,
+    // Answer to "DO U WANT TO REFILL MANA"; unset means yes.
+    #[serde(rename = "refillMana", default)]
+    pub refill_mana: Option<bool>,
+    // Server name -> last time it was picked (RFC3339), for the "lru" server strategy.
+    #[serde(rename = "serverLastUsed", default)]
+    pub server_last_used: HashMap<String, String>,
+    // Free-form label ("main", "alts", ...) for running a subset of accounts.
+    #[serde(default)]
+    pub group: Option<String>,
+    // IANA zone ("Europe/Berlin") for this account's day and `dailyWindow`; unset means Asia/Jakarta.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    // "HH:MM-HH:MM" in the account's timezone. The queue skips the account outside it.
+    #[serde(rename = "dailyWindow", default)]
+    pub daily_window: Option<String>,
+    // If the full restore code is rejected, resend it once in pieces of this many
+    // characters, `codeChunkDelayMs` apart (default 300). Unset never chunks.
+    #[serde(rename = "codeChunkSize", default)]
+    pub code_chunk_size: Option<usize>,
+    #[serde(rename = "codeChunkDelayMs", default)]
+    pub code_chunk_delay_ms: Option<u64>,
+    // More restore codes, stored like `code`. When the game rejects one, the session
+    // sends the next; it fails ALL_CODES_INVALID once every code was rejected.
+    #[serde(default)]
+    pub codes: Vec<String>,
+    // Mode for the daily queue: "daily" (default), "handout", or a sequence
+    // such as "handout,daily" played on one login.
+    #[serde(rename = "runMode", default)]
+    pub run_mode: Option<String>,
+    // Shell command printing this account's session cookie on stdout. Run before
+    // each connect instead of using the shared `cookies` setting.
+    #[serde(rename = "cookieCommand", default)]
+    pub cookie_command: Option<String>,
+    // Extra full runs after the first completes, on the same login: the bot answers 'y'
+    // to the exit prompt and plays the mode again from the menu.
+    #[serde(default)]
+    pub repeat: Option<usize>,
+    // Servers to try in order when `targetServer` reports it is full.
+    #[serde(rename = "fallbackServers", default)]
+    pub fallback_servers: Vec<String>,
+    // Let the normal prompt rules answer prompts matching `premiumSpendPatterns`
+    // instead of declining them.
+    #[serde(rename = "allowGemSpend", default)]
+    pub allow_gem_spend: bool,
+}
+
+/// Status of an account the game reported as banned.
+pub const QUARANTINED_STATUS: &str = "error: ACCOUNT_BANNED (quarantined)";
+
+/// Parses "HH:MM-HH:MM" into its start and end times. The end may be past midnight.
+fn parse_window(raw: &str) -> Option<(chrono::NaiveTime, chrono::NaiveTime)> {
+    let (start, end) = raw.split_once('-')?;
+    let start = chrono::NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+    let end = chrono::NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+    Some((start, end))
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -35,6 +100,453 @@ pub struct Settings {
     // New Individual Admin List
     #[serde(rename = "admins", default)]
     pub admins: Vec<String>,
+    // Also start the queue every this many hours, counted from midnight UTC. Accounts
+    // that finished since the current period began are skipped. Unset runs daily only.
+    #[serde(rename = "runIntervalHours", default)]
+    pub run_interval_hours: Option<u64>,
+    // Discord webhook that gets the end-of-queue report as an embed; add per-account
+    // server and balance lines with `summaryWebhookDetail`.
+    #[serde(rename = "summaryWebhookUrl", default)]
+    pub summary_webhook_url: Option<String>,
+    #[serde(rename = "summaryWebhookDetail", default)]
+    pub summary_webhook_detail: bool,
+    // On shutdown, seconds running sessions get to finish before they are told to
+    // 'exit'. Unset waits 120s.
+    #[serde(rename = "drainTimeoutSecs", default)]
+    pub drain_timeout_secs: Option<u64>,
+    // File that gets one JSON line per finished session (account, runId, sid, outcome,
+    // didWork, durationMs, server). "-" prints the lines to stdout. Unset writes nothing.
+    #[serde(rename = "resultLog", default)]
+    pub result_log: Option<String>,
+    // Allow a second session for an account that is already running. By default it is
+    // refused with ALREADY_RUNNING so two sessions never fight over one login.
+    #[serde(rename = "allowDuplicateSessions", default)]
+    pub allow_duplicate_sessions: bool,
+    #[serde(rename = "session", default)]
+    pub session: SessionSettings,
+}
+
+/// Game session tunables, stored under `settings.session` in the database.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SessionSettings {
+    // After a dropped connection, re-send the last command instead of restarting the game.
+    // Only useful on servers that keep the game running between socket connections.
+    #[serde(rename = "resumeOnReconnect")]
+    pub resume_on_reconnect: bool,
+    // How a resumed session gets the game to print the prompt it was waiting on again:
+    // "last" re-sends the last command, "orient" sends `orientCommand`, "wait" sends
+    // nothing. Without resumeOnReconnect the fresh 'start' prints the menu anyway.
+    #[serde(rename = "resumeReprint")]
+    pub resume_reprint: ResumeReprint,
+    // Named command bundles, e.g. "daily" => ["d", "y", "auto"]. A command matching a key
+    // is sent as its expansion, one step every `macroDelayMs`.
+    pub macros: HashMap<String, Vec<String>>,
+    #[serde(rename = "macroDelayMs")]
+    pub macro_delay_ms: u64,
+    // Send 'stop' (and wait 1.5s) before re-sending 'start' when the game stays silent.
+    #[serde(rename = "stopBeforeStart")]
+    pub stop_before_start: bool,
+    // What to do with commands decided while automation is paused.
+    #[serde(rename = "pausePolicy")]
+    pub pause_policy: PausePolicy,
+    // Case-insensitive phrases that mean the game wants a human (captcha etc.).
+    // Matching ends the session with HUMAN_VERIFICATION_REQUIRED. Empty disables the check.
+    #[serde(rename = "verificationPatterns")]
+    pub verification_patterns: Vec<String>,
+    // Fields of an "output" event whose text is fed to the prompt matcher, in order.
+    // Add "error" / "message" to catch server errors sent outside "data".
+    #[serde(rename = "outputFields")]
+    pub output_fields: Vec<String>,
+    // Layout of socket.io event payloads: "array" is ["output", data], "object" is
+    // {"event": "output", "data": data}.
+    #[serde(rename = "eventEnvelope")]
+    pub event_envelope: EventEnvelope,
+    // Text of the potion quantity prompt. Unset leaves the prompt unanswered.
+    #[serde(rename = "potionPrompt")]
+    pub potion_prompt: Option<String>,
+    // Regex with one capture group for the available potion count, matched against
+    // the output before the prompt. If it doesn't match, '1' is sent.
+    #[serde(rename = "potionCountPattern")]
+    pub potion_count_pattern: String,
+    // How many potions to use per prompt, capped at the available count. Unset uses all.
+    #[serde(rename = "potionQuantity")]
+    pub potion_quantity: Option<u32>,
+    // Backstop against runaway prompt loops: a session that sends more commands
+    // than this ends with COMMAND_LIMIT_EXCEEDED.
+    #[serde(rename = "maxCommands")]
+    pub max_commands: usize,
+    // Generic yes/no prompts: when an output chunk matches `confirmPattern` and no
+    // specific prompt rule answered it, `confirmAnswer` is sent. Empty (the default) disables;
+    // `(?im)\(y/n\)\s*:?\s*$` catches lines ending in "(y/n)".
+    #[serde(rename = "confirmPattern")]
+    pub confirm_pattern: String,
+    #[serde(rename = "confirmAnswer")]
+    pub confirm_answer: String,
+    // Case-insensitive phrases meaning the server is throttling us. Matching ends the
+    // session with RATE_LIMITED and the queue waits `rateLimitCooldownSecs` before retrying.
+    #[serde(rename = "rateLimitPatterns")]
+    pub rate_limit_patterns: Vec<String>,
+    #[serde(rename = "rateLimitCooldownSecs")]
+    pub rate_limit_cooldown_secs: u64,
+    // Which server to log into when several entries match an account's target.
+    #[serde(rename = "serverStrategy")]
+    pub server_strategy: ServerStrategy,
+    // Consecutive heartbeat windows (pingInterval + pingTimeout) without a server
+    // ping before the connection is declared dead. 1 fails on the first miss.
+    #[serde(rename = "maxMissedHeartbeats")]
+    pub max_missed_heartbeats: u32,
+    // Case-insensitive phrases the game uses when today's event can't be played.
+    // Matching ends the session with EVENT_UNAVAILABLE, which counts as done, not failed.
+    #[serde(rename = "eventUnavailablePatterns")]
+    pub event_unavailable_patterns: Vec<String>,
+    // Case-insensitive phrases the game uses when the restore code was already redeemed
+    // today. Matching ends the session with CODE_ALREADY_USED, which counts as done.
+    #[serde(rename = "codeUsedPatterns")]
+    pub code_used_patterns: Vec<String>,
+    // Per-mode end-of-run detection, keyed by mode ("daily" / "handout").
+    // Modes without an entry use `RunMode::default_completion`.
+    pub completion: HashMap<String, CompletionRule>,
+    // Only look for completion keywords in this many characters of output before the
+    // completion prompt (about one screen), so an old unrelated "already" doesn't end
+    // the run. 0 searches the whole history.
+    #[serde(rename = "completionKeywordWindow")]
+    pub completion_keyword_window: usize,
+    // Number of trailing output lines kept as the session's final screen.
+    #[serde(rename = "finalScreenLines")]
+    pub final_screen_lines: usize,
+    // Case-insensitive phrases the site shows after deploying a new client. Matching
+    // ends the session with CLIENT_OUTDATED and stops the queue, since the protocol may
+    // have changed. Empty by default: use the site's exact notice.
+    #[serde(rename = "clientOutdatedPatterns")]
+    pub client_outdated_patterns: Vec<String>,
+    // Alternate menu command per mode ("daily" => "daily"), tried once when the
+    // usual shortcut gets "Invalid Command". Without one the session restarts.
+    #[serde(rename = "fallbackCommands")]
+    pub fallback_commands: HashMap<String, String>,
+    // Keep reading output this long after SESSION_COMPLETE so a late reward summary
+    // still reaches the final screen. Nothing is sent meanwhile. 0 disconnects at once.
+    #[serde(rename = "completionGraceMs")]
+    pub completion_grace_ms: u64,
+    // Times the queue reconnects an account whose menu shortcut was rejected
+    // (INVALID_COMMAND_RESTART) before marking it as errored.
+    #[serde(rename = "invalidCommandRetries")]
+    pub invalid_command_retries: usize,
+    pub timeouts: Timeouts,
+    // Restore codes that are obviously not real, on top of the built-in list.
+    // Matched case-insensitively against the whole decrypted code.
+    #[serde(rename = "placeholderCodes")]
+    pub placeholder_codes: Vec<String>,
+    // Case-insensitive phrases of the maintenance notice. Matching ends the session with
+    // SERVER_MAINTENANCE and the queue waits `maintenanceCooldownSecs` before retrying.
+    // Empty by default: use the full notice, since news lines mention maintenance too.
+    #[serde(rename = "maintenancePatterns")]
+    pub maintenance_patterns: Vec<String>,
+    #[serde(rename = "maintenanceCooldownSecs")]
+    pub maintenance_cooldown_secs: u64,
+    // Characters of each output chunk printed as a [TERMINAL] line. 0 prints it whole.
+    #[serde(rename = "terminalLogChars")]
+    pub terminal_log_chars: usize,
+    // A frame's first command is dropped if it repeats the last command sent for the same
+    // trigger less than this long ago, which happens when a prompt arrives split over two
+    // frames. 0 disables it.
+    #[serde(rename = "duplicateCommandWindowMs")]
+    pub duplicate_command_window_ms: u64,
+    // Case-insensitive phrases the game shows for a banned or suspended account. Matching
+    // ends the session with ACCOUNT_BANNED and the account is quarantined until its status is reset.
+    #[serde(rename = "bannedPatterns")]
+    pub banned_patterns: Vec<String>,
+    // Regex with one capture group for the mana balance shown in the output. While the
+    // last balance read is below `minBalance`, the mana and refill prompts get 'n'.
+    // Empty pattern disables.
+    #[serde(rename = "balancePattern")]
+    pub balance_pattern: String,
+    #[serde(rename = "minBalance")]
+    pub min_balance: Option<u64>,
+    // What the queue does with a session error it has no rule for: "fail" marks the
+    // account as errored, "retry" runs it again up to `unknownOutcomeRetries` times first.
+    #[serde(rename = "unknownOutcome")]
+    pub unknown_outcome: UnknownOutcomePolicy,
+    #[serde(rename = "unknownOutcomeRetries")]
+    pub unknown_outcome_retries: usize,
+    // Cap on bytes received per session; past it the session ends with BANDWIDTH_LIMIT.
+    // Unset is unlimited.
+    #[serde(rename = "maxBytesPerSession")]
+    pub max_bytes_per_session: Option<u64>,
+    // Events sent to start the game and, before a retry with `stopBeforeStart`, to stop it.
+    // The initial start and every retry send the same `startPayload`.
+    #[serde(rename = "startPayload")]
+    pub start_payload: serde_json::Value,
+    #[serde(rename = "stopPayload")]
+    pub stop_payload: serde_json::Value,
+    // Numbered menus answered by label: when `prompt` appears, the index of the first
+    // entry below it whose label contains `label` is sent.
+    #[serde(rename = "menuChoices")]
+    pub menu_choices: Vec<MenuChoice>,
+    // Fixed answers for prompts the built-in rules don't know: when `prompt` appears in
+    // a frame nothing else answered, `reply` is sent.
+    #[serde(rename = "promptReplies")]
+    pub prompt_replies: Vec<PromptReply>,
+    // Prompts that follow each other ("Are you sure?", then "Confirm again?"). Each step
+    // is answered only after the one before it, with its own reply.
+    #[serde(rename = "confirmSequences")]
+    pub confirm_sequences: Vec<ConfirmSequence>,
+    // JSON pointers into the open packet, e.g. "/retry". A present value other than
+    // null or false makes `connect` reconnect (up to `openPacketRetries` times) or,
+    // for `openPacketAbort`, fail with HANDSHAKE_REJECTED.
+    #[serde(rename = "openPacketRetry")]
+    pub open_packet_retry: Vec<String>,
+    #[serde(rename = "openPacketAbort")]
+    pub open_packet_abort: Vec<String>,
+    #[serde(rename = "openPacketRetries")]
+    pub open_packet_retries: u32,
+    // Wait for this many ms without new output before sending the commands a frame
+    // triggered, so a screen streamed in chunks finishes first. 0 sends right away.
+    #[serde(rename = "outputQuietMs")]
+    pub output_quiet_ms: u64,
+    // Itemised rewards, keyed by item name: a regex whose first group is the amount
+    // gained, e.g. "potion" => "(\\d+)x? potions?". Every match in new output is added up.
+    #[serde(rename = "rewardPatterns")]
+    pub reward_patterns: HashMap<String, String>,
+    // Case-insensitive phrases of prompts that spend premium currency. Unless the
+    // account sets `allowGemSpend`, such a prompt is answered with `premiumSpendDecline`
+    // and no other rule answers the same output.
+    #[serde(rename = "premiumSpendPatterns")]
+    pub premium_spend_patterns: Vec<String>,
+    #[serde(rename = "premiumSpendDecline")]
+    pub premium_spend_decline: String,
+    // Socket.io event that carries typed commands, sent as [<inputEvent>, {"input": cmd}].
+    #[serde(rename = "inputEvent")]
+    pub input_event: String,
+    // Harmless command sent right after 'start' so servers that open on a blank
+    // screen print something the prompt rules recognise. Unset sends nothing.
+    #[serde(rename = "orientCommand")]
+    pub orient_command: Option<String>,
+}
+
+/// Every timer the client uses, under settings.session.timeouts.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Timeouts {
+    // Seconds to wait for the open packet on each handshake attempt. A timed-out
+    // attempt reconnects with the next entry; the last one failing ends `connect`.
+    #[serde(rename = "handshakeSecs")]
+    pub handshake_secs: Vec<u64>,
+    // Used when the open packet leaves out pingInterval / pingTimeout.
+    #[serde(rename = "defaultPingIntervalMs")]
+    pub default_ping_interval_ms: u64,
+    #[serde(rename = "defaultPingTimeoutMs")]
+    pub default_ping_timeout_ms: u64,
+    // How often the heartbeat, activity and black-screen checks run.
+    #[serde(rename = "tickSecs")]
+    pub tick_secs: u64,
+    // Wait for the server's '40' after the client's; past it the session ends with
+    // NAMESPACE_JOIN_TIMEOUT. 0 leaves it to the activity timeout.
+    #[serde(rename = "namespaceJoinSecs")]
+    pub namespace_join_secs: u64,
+    // No game output or activity_ping for this long ends the session with ACTIVITY_TIMEOUT.
+    #[serde(rename = "activitySecs")]
+    pub activity_secs: u64,
+    // Silence after 'start' before it is sent again.
+    #[serde(rename = "startRetrySecs")]
+    pub start_retry_secs: u64,
+    // Gap between 'stop' and 'start' when retrying with stopBeforeStart.
+    #[serde(rename = "stopStartGapMs")]
+    pub stop_start_gap_ms: u64,
+    // Time an account's `cookieCommand` gets before it is killed.
+    #[serde(rename = "cookieCommandSecs")]
+    pub cookie_command_secs: u64,
+    // Interval of the [PROGRESS] status line during a session. 0 disables it.
+    #[serde(rename = "progressLogSecs")]
+    pub progress_log_secs: u64,
+    // Wait before reconnecting after an open packet matched `openPacketRetry`.
+    #[serde(rename = "openPacketRetrySecs")]
+    pub open_packet_retry_secs: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            handshake_secs: vec![10, 20, 30],
+            default_ping_interval_ms: 25000,
+            default_ping_timeout_ms: 20000,
+            tick_secs: 5,
+            namespace_join_secs: 15,
+            activity_secs: 180,
+            start_retry_secs: 25,
+            stop_start_gap_ms: 1500,
+            cookie_command_secs: 30,
+            progress_log_secs: 60,
+            open_packet_retry_secs: 5,
+        }
+    }
+}
+
+/// A prompt answered with a fixed reply by the default `PromptMatcher`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PromptReply {
+    // Case-sensitive text to look for in new output.
+    pub prompt: String,
+    pub reply: String,
+}
+
+/// An ordered chain of prompts, each answered once the previous step was.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ConfirmSequence {
+    pub steps: Vec<PromptReply>,
+}
+
+/// A numbered menu the session answers by picking the entry with a given label.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct MenuChoice {
+    // Text printed above the menu.
+    pub prompt: String,
+    // Case-insensitive part of the entry label to pick.
+    pub label: String,
+}
+
+/// How a mode recognises that its work is done.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CompletionRule {
+    // Prompts that ask whether to keep playing; the session ends or answers 'y' there.
+    #[serde(default)]
+    pub prompts: Vec<String>,
+    // Case-insensitive whole words in the output that show the run did its work.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+const PLACEHOLDER_CODES: &[&str] = &["changeme", "change_me", "placeholder", "your_code", "yourcode", "todo", "code", "none", "null"];
+
+impl SessionSettings {
+    /// Whether `code` looks like a placeholder: a built-in or configured dummy
+    /// value, or a single character repeated ("xxxx", "0000").
+    pub fn is_placeholder_code(&self, code: &str) -> bool {
+        let code = code.trim();
+        let mut chars = code.chars();
+        let repeated = match chars.next() {
+            Some(first) => code.len() > 1 && chars.all(|c| c.eq_ignore_ascii_case(&first)),
+            None => false,
+        };
+        repeated
+            || PLACEHOLDER_CODES.iter().any(|p| p.eq_ignore_ascii_case(code))
+            || self.placeholder_codes.iter().any(|p| p.trim().eq_ignore_ascii_case(code))
+    }
+
+    pub fn completion_rule(&self, mode: RunMode) -> CompletionRule {
+        self.completion.get(mode.key()).cloned().unwrap_or_else(|| mode.default_completion())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerStrategy {
+    #[default]
+    First,
+    Random,
+    // Least recently used, from the account's `serverLastUsed`.
+    Lru,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PausePolicy {
+    /// Hold commands and send them in order once resumed.
+    #[default]
+    Buffer,
+    /// Discard commands; the prompts they answered are treated as handled.
+    Drop,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EventEnvelope {
+    /// `["eventName", data]`, the standard socket.io layout.
+    #[default]
+    Array,
+    /// `{"event": "eventName", "data": data}`.
+    Object,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ResumeReprint {
+    /// Re-send the command that was last sent before the drop.
+    #[default]
+    Last,
+    /// Send `orientCommand`, which only makes the game show its current screen.
+    Orient,
+    /// Send nothing and wait for the game to print on its own.
+    Wait,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownOutcomePolicy {
+    #[default]
+    Fail,
+    Retry,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            resume_on_reconnect: false,
+            resume_reprint: ResumeReprint::Last,
+            macros: HashMap::new(),
+            macro_delay_ms: 500,
+            stop_before_start: true,
+            pause_policy: PausePolicy::Buffer,
+            verification_patterns: Vec::new(),
+            output_fields: vec!["data".to_string()],
+            event_envelope: EventEnvelope::Array,
+            potion_prompt: None,
+            potion_count_pattern: r"(?i)(\d+)\s+potions?".to_string(),
+            potion_quantity: None,
+            max_commands: 500,
+            confirm_pattern: String::new(),
+            confirm_answer: "n".to_string(),
+            rate_limit_patterns: vec!["too many attempts".to_string(), "too many requests".to_string(), "rate limit".to_string()],
+            rate_limit_cooldown_secs: 900,
+            server_strategy: ServerStrategy::First,
+            max_missed_heartbeats: 3,
+            event_unavailable_patterns: vec!["event is not available".to_string()],
+            code_used_patterns: vec!["already used today".to_string(), "already been used today".to_string()],
+            completion: HashMap::new(),
+            completion_keyword_window: 1500,
+            final_screen_lines: 15,
+            client_outdated_patterns: Vec::new(),
+            fallback_commands: HashMap::new(),
+            completion_grace_ms: 0,
+            invalid_command_retries: 3,
+            timeouts: Timeouts::default(),
+            placeholder_codes: Vec::new(),
+            maintenance_patterns: Vec::new(),
+            maintenance_cooldown_secs: 1800,
+            terminal_log_chars: 200,
+            duplicate_command_window_ms: 500,
+            banned_patterns: vec!["account has been banned".to_string(), "account is suspended".to_string()],
+            balance_pattern: String::new(),
+            min_balance: None,
+            unknown_outcome: UnknownOutcomePolicy::Fail,
+            unknown_outcome_retries: 2,
+            max_bytes_per_session: None,
+            start_payload: serde_json::json!(["start", {"args": ""}]),
+            stop_payload: serde_json::json!(["stop", {"args": ""}]),
+            menu_choices: Vec::new(),
+            prompt_replies: Vec::new(),
+            confirm_sequences: Vec::new(),
+            open_packet_retry: Vec::new(),
+            open_packet_abort: Vec::new(),
+            open_packet_retries: 3,
+            output_quiet_ms: 0,
+            reward_patterns: HashMap::new(),
+            premium_spend_patterns: vec!["spend gems".to_string(), "use gems".to_string(), "gems to continue".to_string()],
+            premium_spend_decline: "n".to_string(),
+            input_event: "input".to_string(),
+            orient_command: None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -43,6 +555,127 @@ pub struct DbData {
     pub settings: Settings,
 }
 
+/// Outcome of `DbData::validate`. Each entry names the field and, for accounts,
+/// the index and name.
+pub struct Validation {
+    // Values the bot can't work with; loading fails with INVALID_CONFIG.
+    pub errors: Vec<String>,
+    // Values that are ignored or have no effect; the bot still starts.
+    pub warnings: Vec<String>,
+}
+
+impl DbData {
+    /// Checks values serde accepts but the bot can't use, or ignores.
+    pub fn validate(&self) -> Validation {
+        let mut problems = Vec::new();
+        let mut warnings = Vec::new();
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        for (i, acc) in self.accounts.iter().enumerate() {
+            let mut problem = |msg: String| problems.push(format!("accounts[{}] '{}': {}", i, acc.name, msg));
+            // The runtime falls back with a warning for these, so they don't stop the bot.
+            let mut warning = |msg: String| warnings.push(format!("accounts[{}] '{}': {} (ignored)", i, acc.name, msg));
+            if acc.name.trim().is_empty() {
+                problem("name is empty".to_string());
+            } else if let Some(first) = seen.insert(acc.name.as_str(), i) {
+                problem(format!("name is also used by accounts[{}]", first));
+            }
+            for (field, window) in [("loopWindow", &acc.loop_window), ("dailyWindow", &acc.daily_window)] {
+                if let Some(raw) = window.as_deref().filter(|raw| parse_window(raw).is_none()) {
+                    warning(format!("{} '{}' is not HH:MM-HH:MM", field, raw));
+                }
+            }
+            if let Some(Err(_)) = acc.timezone.as_deref().map(|name| name.parse::<chrono_tz::Tz>()) {
+                warning(format!("timezone '{}' is not an IANA zone such as Europe/Berlin", acc.timezone.as_deref().unwrap_or("")));
+            }
+            if let Some(Err(e)) = acc.run_mode.as_deref().map(str::parse::<RunMode>) {
+                warning(format!("runMode: {}", e));
+            }
+            for key in acc.mana_responses.keys().filter(|key| !matches!(key.as_str(), "daily" | "handout")) {
+                problem(format!("manaResponses key '{}' must be daily or handout", key));
+            }
+            if acc.codes.iter().any(|code| code.trim().is_empty()) {
+                problem("codes contains an empty code".to_string());
+            }
+            if acc.code_chunk_size == Some(0) {
+                problem("codeChunkSize must be greater than 0".to_string());
+            }
+            if acc.cookie_command.as_deref().is_some_and(|c| c.trim().is_empty()) {
+                problem("cookieCommand is empty; remove it to use the shared cookie".to_string());
+            }
+            let has_target = acc.target_server.as_deref().is_some_and(|t| t != "Default");
+            if !acc.fallback_servers.is_empty() && !has_target {
+                problem("fallbackServers needs a targetServer other than Default".to_string());
+            }
+        }
+
+        let session = &self.settings.session;
+        let mut problem = |field: &str, msg: String| problems.push(format!("settings.session.{}: {}", field, msg));
+        let mut warning = |field: &str, msg: String| warnings.push(format!("settings.session.{}: {}", field, msg));
+        let reward_fields: Vec<(String, &String)> = session.reward_patterns.iter()
+            .map(|(item, pattern)| (format!("rewardPatterns.{}", item), pattern))
+            .collect();
+        for (field, pattern) in [
+            ("confirmPattern".to_string(), &session.confirm_pattern),
+            ("potionCountPattern".to_string(), &session.potion_count_pattern),
+            ("balancePattern".to_string(), &session.balance_pattern),
+        ].into_iter().chain(reward_fields) {
+            if let Err(e) = regex::Regex::new(pattern) {
+                let reason = e.to_string().lines().last().unwrap_or("").trim_start_matches("error: ").to_string();
+                problem(&field, format!("'{}' is not a valid regex: {}", pattern, reason));
+            }
+        }
+        for (field, payload) in [("startPayload", &session.start_payload), ("stopPayload", &session.stop_payload)] {
+            if !payload.get(0).is_some_and(serde_json::Value::is_string) {
+                problem(field, "must be an array starting with the event name, e.g. [\"start\", {\"args\": \"\"}]".to_string());
+            }
+        }
+        for (field, pointers) in [("openPacketRetry", &session.open_packet_retry), ("openPacketAbort", &session.open_packet_abort)] {
+            for pointer in pointers.iter().filter(|p| !p.starts_with('/')) {
+                problem(field, format!("'{}' must be a JSON pointer starting with '/'", pointer));
+            }
+        }
+        for (i, choice) in session.menu_choices.iter().enumerate() {
+            if choice.prompt.is_empty() || choice.label.is_empty() {
+                problem(&format!("menuChoices[{}]", i), "needs both prompt and label".to_string());
+            }
+        }
+        for (i, reply) in session.prompt_replies.iter().enumerate() {
+            if reply.prompt.is_empty() || reply.reply.is_empty() {
+                problem(&format!("promptReplies[{}]", i), "needs both prompt and reply".to_string());
+            }
+        }
+        for (i, sequence) in session.confirm_sequences.iter().enumerate() {
+            if sequence.steps.is_empty() || sequence.steps.iter().any(|step| step.prompt.is_empty() || step.reply.is_empty()) {
+                problem(&format!("confirmSequences[{}]", i), "needs at least one step, each with a prompt and a reply".to_string());
+            }
+        }
+        if session.max_commands == 0 {
+            problem("maxCommands", "must be greater than 0".to_string());
+        }
+        if session.timeouts.handshake_secs.contains(&0) {
+            problem("timeouts.handshakeSecs", "entries must be greater than 0".to_string());
+        }
+        if session.potion_quantity == Some(0) {
+            problem("potionQuantity", "must be greater than 0".to_string());
+        }
+        if session.potion_quantity.is_some() && session.potion_prompt.as_deref().unwrap_or("").is_empty() {
+            warning("potionQuantity", "has no effect without potionPrompt".to_string());
+        }
+        if session.resume_reprint == ResumeReprint::Orient && session.orient_command.as_deref().unwrap_or("").is_empty() {
+            problem("resumeReprint", "\"orient\" needs orientCommand".to_string());
+        }
+        if session.min_balance.is_some() && session.balance_pattern.is_empty() {
+            warning("minBalance", "has no effect without balancePattern".to_string());
+        }
+        for (field, keys) in [("completion", session.completion.keys().collect::<Vec<_>>()), ("fallbackCommands", session.fallback_commands.keys().collect())] {
+            for key in keys.into_iter().filter(|key| !matches!(key.as_str(), "daily" | "handout")) {
+                problem(field, format!("key '{}' must be daily or handout", key));
+            }
+        }
+        Validation { errors: problems, warnings }
+    }
+}
+
 pub struct Database {
     pub data: DbData,
 }
@@ -50,18 +683,32 @@ pub struct Database {
 use magic_crypt::MagicCryptTrait;
 
 impl Account {
+    /// True if this account can get a cookie when the shared one is unset.
+    pub fn has_cookie_source(&self, shared: &str) -> bool {
+        !shared.is_empty() || self.cookie_command.is_some()
+    }
+
     pub fn decrypt_code(&self) -> String {
+        Self::decrypt_code_str(&self.code)
+    }
+
+    /// The decrypted `codes`, in the order they should be tried after `code`.
+    pub fn decrypt_spare_codes(&self) -> Vec<String> {
+        self.codes.iter().map(|code| Self::decrypt_code_str(code)).collect()
+    }
+
+    fn decrypt_code_str(stored: &str) -> String {
         let key = std::env::var("ENCRYPTION_KEY").unwrap_or_else(|_| "default_insecure_key".to_string());
         if key == "default_insecure_key" {
             // Warn only once or just proceed? For now, just return raw if likely not encrypted or using default
-             return self.code.clone();
+             return stored.to_string();
         }
         let mc = magic_crypt::new_magic_crypt!(&key, 256);
-        match mc.decrypt_base64_to_string(&self.code) {
+        match mc.decrypt_base64_to_string(stored) {
              Ok(decrypted) => decrypted,
              Err(_) => {
                  // Fallback: maybe it's not encrypted yet? Return raw.
-                 self.code.clone()
+                 stored.to_string()
              }
         }
     }
@@ -74,6 +721,93 @@ impl Account {
         let mc = magic_crypt::new_magic_crypt!(&key, 256);
         mc.encrypt_str_to_base64(raw_code)
     }
+
+    pub fn in_group(&self, group: &str) -> bool {
+        self.group.as_deref().is_some_and(|g| g.eq_ignore_ascii_case(group))
+    }
+
+    /// Parses `loop_window` into its start and end times. The end may be past midnight.
+    pub fn loop_window(&self) -> Option<(chrono::NaiveTime, chrono::NaiveTime)> {
+        let raw = self.loop_window.as_deref()?;
+        let parsed = parse_window(raw);
+        if parsed.is_none() {
+            println!("[WARN] Ignoring invalid loopWindow '{}' on {} (expected HH:MM-HH:MM)", raw, self.name);
+        }
+        parsed
+    }
+
+    /// The account's timezone, falling back to Asia/Jakarta when unset or unknown.
+    pub fn tz(&self) -> chrono_tz::Tz {
+        match self.timezone.as_deref().map(|name| name.parse::<chrono_tz::Tz>()) {
+            Some(Ok(tz)) => tz,
+            Some(Err(_)) => {
+                println!("[WARN] Unknown timezone '{}' on {}, using Asia/Jakarta", self.timezone.as_deref().unwrap_or(""), self.name);
+                chrono_tz::Asia::Jakarta
+            },
+            None => chrono_tz::Asia::Jakarta,
+        }
+    }
+
+    /// Parses `daily_window` into its start and end times. The end may be past midnight.
+    pub fn daily_window(&self) -> Option<(chrono::NaiveTime, chrono::NaiveTime)> {
+        let raw = self.daily_window.as_deref()?;
+        let parsed = parse_window(raw);
+        if parsed.is_none() {
+            println!("[WARN] Ignoring invalid dailyWindow '{}' on {} (expected HH:MM-HH:MM)", raw, self.name);
+        }
+        parsed
+    }
+
+    /// Whether `now` falls inside the account's `dailyWindow`, in its own timezone.
+    /// Accounts without a window are always inside.
+    pub fn is_within_window(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        let Some((start, end)) = self.daily_window() else {
+            return true;
+        };
+        let local = now.with_timezone(&self.tz()).time();
+        if start <= end { local >= start && local < end } else { local >= start || local < end }
+    }
+
+    /// Whether `last_run` falls on the same calendar day as `now` in the account's timezone.
+    pub fn ran_today(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        let tz = self.tz();
+        self.last_run.as_deref()
+            .and_then(|raw| chrono::DateTime::parse_from_rfc3339(raw).ok())
+            .is_some_and(|last| last.with_timezone(&tz).date_naive() == now.with_timezone(&tz).date_naive())
+    }
+
+    /// True if the account was banned; resets and scheduled runs leave it alone.
+    pub fn is_quarantined(&self) -> bool {
+        self.status == QUARANTINED_STATUS
+    }
+
+    /// True if the last run was at or after `start`.
+    pub fn ran_since(&self, start: chrono::DateTime<chrono::Utc>) -> bool {
+        self.last_run.as_deref()
+            .and_then(|raw| chrono::DateTime::parse_from_rfc3339(raw).ok())
+            .is_some_and(|last| last >= start)
+    }
+
+    /// The mode the daily queue runs this account in.
+    pub fn run_mode(&self) -> RunMode {
+        match self.run_mode.as_deref().map(str::parse::<RunMode>) {
+            Some(Ok(mode)) => mode,
+            Some(Err(e)) => {
+                println!("[WARN] Ignoring runMode on {}: {}", self.name, e);
+                RunMode::Daily
+            },
+            None => RunMode::Daily,
+        }
+    }
+
+    /// Returns the answer for the `hit`-th (0-based) mana prompt of a session.
+    pub fn mana_response(&self, mode: RunMode, hit: usize) -> String {
+        let responses = match self.mana_responses.get(mode.key()) {
+            Some(list) if !list.is_empty() => list.clone(),
+            _ => mode.default_mana_responses(),
+        };
+        responses[hit.min(responses.len() - 1)].clone()
+    }
 }
 
 impl Database {
@@ -147,7 +881,20 @@ impl Database {
         };
 
         match serde_json::from_str::<DbData>(&content) {
-            Ok(data) => Ok(Self { data }),
+            Ok(data) => {
+                let Validation { errors: problems, warnings } = data.validate();
+                for warning in &warnings {
+                    println!("[WARN] Database: {}", warning);
+                }
+                if problems.is_empty() {
+                    return Ok(Self { data });
+                }
+                println!("[ERROR] Database has {} invalid setting(s):", problems.len());
+                for problem in &problems {
+                    println!("[ERROR]   {}", problem);
+                }
+                Err(format!("INVALID_CONFIG: {} problem(s), first: {}", problems.len(), problems[0]).into())
+            },
             Err(e) => {
                 println!("[ERROR] Failed to parse database JSON: {}", e);
                 // If parsing fails, we might as well return the error, 
@@ -192,6 +939,14 @@ impl Database {
         Ok(())
     }
 
+    pub fn record_server_use(&mut self, name: &str, server: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(acc) = self.data.accounts.iter_mut().find(|a| a.name == name) {
+            acc.server_last_used.insert(server.to_string(), chrono::Utc::now().to_rfc3339());
+            self.save()?;
+        }
+        Ok(())
+    }
+
     pub fn add_account(&mut self, account: Account) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         self.data.accounts.retain(|a| a.name != account.name);
         self.data.accounts.push(account);
@@ -215,6 +970,29 @@ impl Database {
         self.save()
     }
 
+    /// Daily reset: like `reset_all_statuses`, but an account with its own timezone
+    /// keeps "done" if it already finished during its local day.
+    pub fn reset_daily_statuses(&mut self, now: chrono::DateTime<chrono::Utc>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for acc in self.data.accounts.iter_mut() {
+            if acc.is_quarantined() || (acc.timezone.is_some() && acc.status == "done" && acc.ran_today(now)) {
+                continue;
+            }
+            acc.status = "pending".to_string();
+        }
+        self.save()
+    }
+
+    /// Makes every account pending again except those that finished since `period_start`.
+    pub fn reset_for_period(&mut self, period_start: chrono::DateTime<chrono::Utc>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for acc in self.data.accounts.iter_mut() {
+            if acc.is_quarantined() || (acc.status == "done" && acc.ran_since(period_start)) {
+                continue;
+            }
+            acc.status = "pending".to_string();
+        }
+        self.save()
+    }
+
     pub fn toggle_ping(&mut self, user_id: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
         let mut new_state = false;
         let mut first = true;
@@ -272,9 +1050,22 @@ impl Database {
         }
     }
 
+    pub fn set_group(&mut self, name: &str, group: Option<&str>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(acc) = self.data.accounts.iter_mut().find(|a| a.name == name) {
+            acc.group = group.map(|g| g.to_string());
+            self.save()
+        } else {
+            Err("Account not found".into())
+        }
+    }
+
+    pub fn has_group(&self, group: &str) -> bool {
+        self.data.accounts.iter().any(|a| a.in_group(group))
+    }
+
     pub fn get_handout_accounts(&self) -> Vec<Account> {
         self.data.accounts.iter()
-            .filter(|a| a.handout_enabled)
+            .filter(|a| a.handout_enabled && !a.is_quarantined())
             .cloned()
             .collect()
     }
@@ -1,20 +1,249 @@
 mod protocol;
 mod db;
 mod default_db;
+mod cli;
+mod report;
+mod control;
 
+use protocol::session::{format_trace, ContinuePolicy};
 use protocol::socket::{EvertextClient, RunMode};
-use db::{Database, Account};
+use db::{Database, Account, UnknownOutcomePolicy, QUARANTINED_STATUS};
+use report::{DiscordSummaryReporter, ReportBuilder, SessionResult};
 
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex};
 use serenity::all::*;
 use serenity::async_trait;
 use chrono::{Utc, Timelike};
 use chrono_tz::Asia::Jakarta;
+use clap::Parser;
+use cli::{Cli, Commands};
 
+/// Keeps an account farming while the Jakarta clock is inside its `loopWindow`.
+fn loop_window_policy(acc: &Account) -> Option<ContinuePolicy> {
+    let (start, end) = acc.loop_window()?;
+    Some(Box::new(move || {
+        let now = Utc::now().with_timezone(&Jakarta).time();
+        if start <= end { now >= start && now < end } else { now >= start || now < end }
+    }))
+}
+
+/// Errors after which the same account is simply retried on a fresh connection.
+fn is_connection_issue(err_str: &str) -> bool {
+    err_str.contains("IDLE_TIMEOUT") || err_str.contains("CONNECTION_FAILED") || err_str.contains("SERVER_DISCONNECT") || err_str.contains("SOCKET_ERROR") || err_str.contains("NAMESPACE_JOIN_TIMEOUT") || err_str.contains("Connection handshake timed out")
+}
+
+/// How a session ended, read from its error code. Shared by the queue, the handout
+/// queue and force runs so the same error is treated alike everywhere.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Completed,
+    EventUnavailable,
+    CodeAlreadyUsed,
+    InvalidCommand,
+    Zigza,
+    AllCodesInvalid,
+    ServerFull,
+    Aborted,
+    Maintenance,
+    Banned,
+    VerificationRequired,
+    ClientOutdated,
+    InvalidConfig,
+    LoginRequired,
+    RateLimited,
+    ConnectionIssue,
+    Failed,
+}
+
+impl Outcome {
+    /// Status that keeps the account out of later queues until someone changes it.
+    fn hold_status(self) -> Option<&'static str> {
+        match self {
+            Outcome::Banned => Some(QUARANTINED_STATUS),
+            Outcome::AllCodesInvalid => Some("error: All restore codes rejected"),
+            Outcome::VerificationRequired => Some("error: Human verification required"),
+            _ => None,
+        }
+    }
+}
+
+fn classify(err_str: &str) -> Outcome {
+    const CODES: [(&str, Outcome); 15] = [
+        ("SESSION_COMPLETE", Outcome::Completed),
+        ("EVENT_UNAVAILABLE", Outcome::EventUnavailable),
+        ("CODE_ALREADY_USED", Outcome::CodeAlreadyUsed),
+        ("INVALID_COMMAND_RESTART", Outcome::InvalidCommand),
+        ("ZIGZA_DETECTED", Outcome::Zigza),
+        ("ALL_CODES_INVALID", Outcome::AllCodesInvalid),
+        ("SERVER_FULL", Outcome::ServerFull),
+        ("SESSION_ABORTED", Outcome::Aborted),
+        ("SERVER_MAINTENANCE", Outcome::Maintenance),
+        ("ACCOUNT_BANNED", Outcome::Banned),
+        ("HUMAN_VERIFICATION_REQUIRED", Outcome::VerificationRequired),
+        ("CLIENT_OUTDATED", Outcome::ClientOutdated),
+        ("INVALID_CONFIG", Outcome::InvalidConfig),
+        ("LOGIN_REQUIRED", Outcome::LoginRequired),
+        ("RATE_LIMITED", Outcome::RateLimited),
+    ];
+    match CODES.iter().find(|(code, _)| err_str.contains(code)) {
+        Some((_, outcome)) => *outcome,
+        None if is_connection_issue(err_str) => Outcome::ConnectionIssue,
+        None => Outcome::Failed,
+    }
+}
+
+/// Copies the client's output lines, prefixed with the account name, to `hub`
+/// until the client is dropped.
+fn forward_output(client: &EvertextClient, account: &str, hub: &broadcast::Sender<String>) {
+    let mut rx = client.subscribe_output();
+    let hub = hub.clone();
+    let account = account.to_string();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(line) => { let _ = hub.send(format!("{}: {}", account, line)); },
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Running sessions' abort flags, keyed by account name.
+type AbortFlags = Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>;
+
+/// Registers `account` as running and returns the flag `abort_account` sets for it.
+/// Fails with ALREADY_RUNNING while the account has a session registered, unless
+/// `allowDuplicateSessions` is set.
+async fn register_abort(aborts: &AbortFlags, account: &str, allow_duplicates: bool) -> Result<Arc<AtomicBool>, String> {
+    let mut aborts = aborts.lock().await;
+    if !allow_duplicates && aborts.contains_key(account) {
+        return Err("ALREADY_RUNNING".to_string());
+    }
+    let flag = Arc::new(AtomicBool::new(false));
+    aborts.insert(account.to_string(), Arc::clone(&flag));
+    Ok(flag)
+}
+
+/// Unregisters `account`, unless a duplicate session registered it again since.
+async fn release_abort(aborts: &AbortFlags, account: &str, flag: &Arc<AtomicBool>) {
+    let mut aborts = aborts.lock().await;
+    if aborts.get(account).is_some_and(|current| Arc::ptr_eq(current, flag)) {
+        aborts.remove(account);
+    }
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix (what container platforms send on deploy).
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = term.recv() => {},
+                }
+            },
+            Err(_) => { let _ = tokio::signal::ctrl_c().await; },
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// On a shutdown signal: stop starting queues and sessions, give the running ones
+/// `drainTimeoutSecs` to finish, then abort the rest (they send 'exit') and exit.
+async fn drain_on_shutdown(handler: Handler) {
+    shutdown_signal().await;
+    tokio::spawn(async {
+        shutdown_signal().await;
+        println!("[WARN] Second shutdown signal. Exiting now.");
+        std::process::exit(1);
+    });
+    handler.draining.store(true, Ordering::Relaxed);
+    *handler.is_processing.lock().await = false;
+    let (timeout, tick) = {
+        let db = handler.db.lock().await;
+        (db.data.settings.drain_timeout_secs.unwrap_or(120), db.data.settings.session.timeouts.tick_secs.max(1))
+    };
+    let running = |aborts: &HashMap<String, Arc<AtomicBool>>| aborts.keys().cloned().collect::<Vec<_>>().join(", ");
+    println!("[INFO] Shutdown requested. Waiting up to {}s for running sessions to finish: {}", timeout, running(&*handler.aborts.lock().await));
+
+    let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(timeout);
+    while !handler.aborts.lock().await.is_empty() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    }
+    {
+        let aborts = handler.aborts.lock().await;
+        if !aborts.is_empty() {
+            println!("[WARN] Drain timeout reached. Aborting: {}", running(&aborts));
+            for flag in aborts.values() {
+                flag.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+    // Aborted sessions send 'exit' on their next tick.
+    let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(tick + 5);
+    while !handler.aborts.lock().await.is_empty() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    }
+    // A session leaves the map just before its status is saved; give that a moment
+    // and hold the database lock so no write is cut off.
+    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    let _db = handler.db.lock().await;
+    println!("[INFO] Shutdown complete.");
+    std::process::exit(0);
+}
+
+/// Remembers which server the client's last session logged into, for the "lru" strategy.
+async fn record_server_use(db: &Arc<Mutex<Database>>, account: &str, client: &EvertextClient) {
+    if let Some(server) = client.selected_server() {
+        let mut db = db.lock().await;
+        let _ = db.record_server_use(account, &server.name);
+    }
+}
+
+/// Writes the client's last session to `resultLog` as one JSON line, if it is set.
+async fn log_result(db: &Arc<Mutex<Database>>, account: &str, client: &EvertextClient, result: &Result<(), Box<dyn std::error::Error + Send + Sync>>, duration: std::time::Duration) {
+    let Some(sink) = db.lock().await.data.settings.result_log.clone().filter(|s| !s.is_empty()) else {
+        return;
+    };
+    let outcome = match result {
+        Ok(()) => "OK".to_string(),
+        Err(e) => e.to_string().split(|c: char| c.is_whitespace() || c == ':').next().unwrap_or_default().to_string(),
+    };
+    let line = SessionResult {
+        account: account.to_string(),
+        run_id: client.run_id(),
+        sid: client.sid().to_string(),
+        outcome,
+        did_work: client.did_work(),
+        duration_ms: duration.as_millis() as u64,
+        server: client.selected_server().map(|s| s.name),
+    };
+    if let Err(e) = line.write_to(&sink) {
+        println!("[WARN] Could not write the result line to {}: {}", sink, e);
+    }
+}
+
+#[derive(Clone)]
 struct Handler {
     db: Arc<Mutex<Database>>,
     is_processing: Arc<Mutex<bool>>,
+    // Shared with every running client; while set, commands are held or dropped.
+    paused: Arc<AtomicBool>,
+    // Output lines of all clients, "<account>: <line>", served on the control API's /output.
+    output: broadcast::Sender<String>,
+    // Set for one account by /abort_account to end just its session.
+    aborts: AbortFlags,
+    // Set once a shutdown signal arrives; no new queue or session starts after it.
+    draining: Arc<AtomicBool>,
 }
 
 impl Handler {
@@ -62,33 +291,45 @@ impl Handler {
         }
     }
 
-    async fn process_queue(&self, ctx: Context, user_id_filter: Option<String>, source_channel: Option<ChannelId>) {
+    /// Starts the daily queue in the background. Returns false when it didn't start
+    /// because the bot is shutting down or a queue is already running.
+    async fn process_queue(&self, ctx: Context, user_id_filter: Option<String>, group_filter: Option<String>, source_channel: Option<ChannelId>) -> bool {
+        if self.draining.load(Ordering::Relaxed) {
+            println!("[WARN] Shutting down. Not starting the queue.");
+            return false;
+        }
+        {
+            let mut is_proc = self.is_processing.lock().await;
+            if *is_proc {
+                drop(is_proc);
+                if let Some(chan) = source_channel {
+                    let _ = chan.say(&ctx.http, "[WARN] Queue Manager: Already in progress.").await;
+                }
+                return false;
+            }
+            *is_proc = true;
+        }
         let db_clone = Arc::clone(&self.db);
         let processing_clone = Arc::clone(&self.is_processing);
+        let paused = Arc::clone(&self.paused);
+        let output = self.output.clone();
+        let aborts = Arc::clone(&self.aborts);
         let http_clone = ctx.http.clone();
 
         tokio::spawn(async move {
-            let already_running = {
-                let mut is_proc = processing_clone.lock().await;
-                if *is_proc {
-                    true
-                } else {
-                    *is_proc = true;
-                    false
-                }
-            };
-
-            if already_running {
-                if let Some(chan) = source_channel {
-                    let _ = chan.say(&http_clone, "[WARN] Queue Manager: Already in progress.").await;
-                }
-                return;
-            }
-
             if let Some(chan) = source_channel {
                     let _ = chan.say(&http_clone, "[INFO] Queue Manager: Starting automation sequence...").await;
             }
 
+            // Progress of sessions cut off by connection issues, keyed by account name.
+            let mut resume_points = HashMap::new();
+            // Retries spent on unrecognised errors, keyed by account name.
+            let mut unknown_retries: HashMap<String, usize> = HashMap::new();
+            let mut invalid_command_retries: HashMap<String, usize> = HashMap::new();
+            // Accounts another session was already running, left out for the rest of this run.
+            let mut skipped: HashSet<String> = HashSet::new();
+            let mut report = ReportBuilder::new();
+
             loop {
                 // Check if we were told to stop
                 {
@@ -114,6 +355,12 @@ impl Handler {
                         });
                     }
                     
+                    if let Some(group) = &group_filter {
+                        accs.retain(|a| a.in_group(group));
+                    }
+                    let now = Utc::now();
+                    accs.retain(|a| a.is_within_window(now) && !skipped.contains(&a.name));
+                    
                     println!("[DEBUG] Found {} pending accounts for this user.", accs.len());
                     
                     // Explicitly prioritize:
@@ -131,20 +378,71 @@ impl Handler {
                     None => break,
                 };
                 
-                let cookie = {
+                let (cookie, session_settings, allow_duplicates) = {
                     let db = db_clone.lock().await;
-                    db.data.settings.cookies.clone().unwrap_or_default()
+                    (db.data.settings.cookies.clone().unwrap_or_default(), db.data.settings.session.clone(), db.data.settings.allow_duplicate_sessions)
                 };
 
-                if cookie.is_empty() {
+                if !acc.has_cookie_source(&cookie) {
                      break;
                 }
 
-                match EvertextClient::connect(&cookie).await {
+                if session_settings.is_placeholder_code(&acc.decrypt_code()) {
+                    report.failure(&acc.name, "placeholder_code", "PLACEHOLDER_CODE", std::time::Duration::ZERO);
+                    {
+                        let mut db = db_clone.lock().await;
+                        let _ = db.update_status(&acc.name, "error: PLACEHOLDER_CODE");
+                    }
+                    if let Some(chan) = source_channel {
+                        let _ = chan.say(&http_clone, format!("[ERROR] **{}** has a placeholder restore code. Skipping it.", acc.name)).await;
+                    }
+                    Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[ERROR] Automation: **{}** has a placeholder restore code. Re-add it with the real code.", acc.name), source_channel).await;
+                    continue;
+                }
+
+                let abort = match register_abort(&aborts, &acc.name, allow_duplicates).await {
+                    Ok(flag) => flag,
+                    Err(code) => {
+                        // Its own session sets the status; this queue just leaves it alone.
+                        report.failure(&acc.name, "already_running", &code, std::time::Duration::ZERO);
+                        skipped.insert(acc.name.clone());
+                        if let Some(chan) = source_channel {
+                            let _ = chan.say(&http_clone, format!("[WARN] **{}** is already running in another session. Skipping it.", acc.name)).await;
+                        }
+                        continue;
+                    }
+                };
+                let session_started = std::time::Instant::now();
+                match EvertextClient::connect_account(&acc, &cookie, &session_settings).await {
                     Ok(mut client) => {
+                        client.set_pause_flag(Arc::clone(&paused));
+                        client.set_abort_flag(Arc::clone(&abort));
+                        forward_output(&client, &acc.name, &output);
+                        let running = Arc::clone(&processing_clone);
+                        client.set_repeat_guard(Box::new(move || running.try_lock().map_or(true, |running| *running)));
+                        if let Some(point) = resume_points.remove(&acc.name) {
+                            client.resume_from(point);
+                        }
+                        if let Some(policy) = loop_window_policy(&acc) {
+                            client.set_continue_policy(policy);
+                        }
                         let decrypted_code = acc.decrypt_code();
-                        match client.run_loop(&acc, &decrypted_code, RunMode::Daily).await {
+                        let result = client.run_loop(&acc, &decrypted_code, acc.run_mode()).await;
+                        release_abort(&aborts, &acc.name, &abort).await;
+                        let session_time = session_started.elapsed();
+                        log_result(&db_clone, &acc.name, &client, &result, session_time).await;
+                        record_server_use(&db_clone, &acc.name, &client).await;
+                        report.final_screen(&acc.name, client.final_screen());
+                        if let Some(balance) = client.balance() {
+                            report.balance(&acc.name, balance);
+                        }
+                        report.rewards(&acc.name, &client.rewards());
+                        if let Some(server) = client.selected_server() {
+                            report.server(&acc.name, server);
+                        }
+                        match result {
                              Ok(_) => {
+                                report.success("completed", session_time);
                                 {
                                     let mut db = db_clone.lock().await;
                                     let _ = db.update_status(&acc.name, "done");
@@ -156,72 +454,226 @@ impl Handler {
                             },
                             Err(e) => {
                                 let err_str = e.to_string();
-                                
-                                if err_str.contains("SESSION_COMPLETE") {
-                                    {
-                                        let mut db = db_clone.lock().await;
-                                        let _ = db.update_status(&acc.name, "done");
-                                    }
-                                    if let Some(chan) = source_channel {
-                                        let _ = chan.say(&http_clone, format!("[SUCCESS] **{}** completed.", acc.name)).await;
-                                    }
-                                    Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[SUCCESS] Automation: **{}** completed through prompt flow.", acc.name), source_channel).await;
-
-                                } else if err_str.contains("INVALID_COMMAND_RESTART") {
-                                    if let Some(chan) = source_channel {
-                                         let _ = chan.say(&http_clone, format!("[WARN] Invalid Command on **{}**. Restarting session immediately.", acc.name)).await;
-                                    }
-                                    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-
-                                } else if err_str.contains("ZIGZA_DETECTED") {
-                                    if let Some(chan) = source_channel {
-                                        let _ = chan.say(&http_clone, format!("[WARN] Zigza error on **{}**. Waiting 10 mins before retry.", acc.name)).await;
-                                    }
-                                    Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[WARN] Automation: Zigza detected on **{}**. Retrying in 10m.", acc.name), source_channel).await;
-                                    {
-                                        let mut db = db_clone.lock().await;
-                                        let _ = db.update_status(&acc.name, "error: Zigza Retrying");
-                                    }
-                                    tokio::time::sleep(tokio::time::Duration::from_secs(600)).await;
-
-                                } else if err_str.contains("SERVER_FULL") {
-                                    if let Some(chan) = source_channel {
-                                        let _ = chan.say(&http_clone, format!("[WARN] Server Full. Retrying **{}** in 5 mins.", acc.name)).await;
-                                    }
-                                    Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[WARN] Automation: Server full. Retrying **{}** in 5m.", acc.name), source_channel).await;
-                                    tokio::time::sleep(tokio::time::Duration::from_secs(300)).await;
-
-                                } else if err_str.contains("LOGIN_REQUIRED") {
-                                    if let Some(chan) = source_channel {
-                                        let _ = chan.say(&http_clone, "⚠️ **CRITICAL: Session cookie expired!** Stopping queue.").await;
-                                    }
-                                    Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), "⚠️ **[CRITICAL] Automation: Session cookie expired!** Stopping queue.".to_string(), source_channel).await;
-                                    break;
-
-                                } else if err_str.contains("IDLE_TIMEOUT") || err_str.contains("CONNECTION_FAILED") || err_str.contains("SERVER_DISCONNECT") || err_str.contains("Connection handshake timed out") {
-                                    if let Some(chan) = source_channel {
-                                        let _ = chan.say(&http_clone, format!("[WARN] Connection issue on **{}** (Reason: {}). Retrying in 5s...", acc.name, err_str)).await;
-                                    }
-                                    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-
-                                } else {
-                                    {
-                                        let mut db = db_clone.lock().await;
-                                        let _ = db.update_status(&acc.name, &format!("error: {}", err_str));
-                                    }
-                                    if let Some(chan) = source_channel {
-                                        let _ = chan.say(&http_clone, format!("[ERROR] **{}** failed: {}", acc.name, err_str)).await;
-                                    }
-                                    Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[ERROR] Automation: **{}** failed. Reason: {}", acc.name, err_str), source_channel).await;
+                                let outcome = classify(&err_str);
+                                if let Some(status) = outcome.hold_status() {
+                                    let mut db = db_clone.lock().await;
+                                    let _ = db.update_status(&acc.name, status);
+                                }
+                                match outcome {
+                                    Outcome::Completed => {
+                                        report.success("completed", session_time);
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, "done");
+                                        }
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("[SUCCESS] **{}** completed.", acc.name)).await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[SUCCESS] Automation: **{}** completed through prompt flow.", acc.name), source_channel).await;
+                                    },
+                                    Outcome::EventUnavailable => {
+                                        report.success("event_unavailable", session_time);
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, "done");
+                                        }
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("[INFO] Event not available for **{}**. Marked as done.", acc.name)).await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[INFO] Automation: Event not available for **{}**. Skipped for today.", acc.name), source_channel).await;
+                                    },
+                                    Outcome::CodeAlreadyUsed => {
+                                        report.success("code_already_used", session_time);
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, "done");
+                                        }
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("[INFO] **{}**'s restore code was already used today. Marked as done.", acc.name)).await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[INFO] Automation: **{}**'s restore code was already used today. Skipped for today.", acc.name), source_channel).await;
+                                    },
+                                    Outcome::InvalidCommand => {
+                                        report.failure(&acc.name, "invalid_command", &err_str, session_time);
+                                        let tries = invalid_command_retries.entry(acc.name.clone()).or_insert(0);
+                                        *tries += 1;
+                                        if *tries > session_settings.invalid_command_retries {
+                                            {
+                                                let mut db = db_clone.lock().await;
+                                                let _ = db.update_status(&acc.name, "error: INVALID_COMMAND");
+                                            }
+                                            if let Some(chan) = source_channel {
+                                                let _ = chan.say(&http_clone, format!("[ERROR] **{}** kept rejecting the menu command. Skipping it.", acc.name)).await;
+                                            }
+                                            Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[ERROR] Automation: **{}** rejected the menu command {} times. Skipped.", acc.name, tries), source_channel).await;
+                                        } else {
+                                            if let Some(chan) = source_channel {
+                                                 let _ = chan.say(&http_clone, format!("[WARN] Invalid Command on **{}**. Restarting session ({}/{}).", acc.name, tries, session_settings.invalid_command_retries)).await;
+                                            }
+                                            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                                        }
+                                    },
+                                    Outcome::Zigza => {
+                                        report.failure(&acc.name, "zigza", &err_str, session_time);
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("[WARN] Zigza error on **{}**. Waiting 10 mins before retry.", acc.name)).await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[WARN] Automation: Zigza detected on **{}**. Retrying in 10m.", acc.name), source_channel).await;
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, "error: Zigza Retrying");
+                                        }
+                                        tokio::time::sleep(tokio::time::Duration::from_secs(600)).await;
+                                    },
+                                    Outcome::AllCodesInvalid => {
+                                        report.failure(&acc.name, "all_codes_invalid", &err_str, session_time);
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("⚠️ Every restore code of **{}** was rejected. Skipping it until its codes are updated.", acc.name)).await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("⚠️ **[ACTION NEEDED] Automation: every restore code of {} was rejected.** Account skipped.", acc.name), source_channel).await;
+                                    },
+                                    Outcome::ServerFull => {
+                                        report.failure(&acc.name, "server_full", &err_str, session_time);
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("[WARN] Server Full. Retrying **{}** in 5 mins.", acc.name)).await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[WARN] Automation: Server full. Retrying **{}** in 5m.", acc.name), source_channel).await;
+                                        tokio::time::sleep(tokio::time::Duration::from_secs(300)).await;
+                                    },
+                                    Outcome::Aborted => {
+                                        report.failure(&acc.name, "aborted", &err_str, session_time);
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, "error: aborted");
+                                        }
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("[WARN] **{}** was aborted. Moving on.", acc.name)).await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[WARN] Automation: **{}** was aborted by an admin.", acc.name), source_channel).await;
+                                    },
+                                    Outcome::Maintenance => {
+                                        report.failure(&acc.name, "maintenance", &err_str, session_time);
+                                        let cooldown = session_settings.maintenance_cooldown_secs;
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("[WARN] Game is under maintenance. Retrying **{}** in {} mins.", acc.name, cooldown / 60)).await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("🛠️ **Automation: the game is under maintenance.** Pausing the queue for {} mins, then retrying **{}**.", cooldown / 60, acc.name), source_channel).await;
+                                        tokio::time::sleep(tokio::time::Duration::from_secs(cooldown)).await;
+                                    },
+                                    Outcome::Banned => {
+                                        report.failure(&acc.name, "banned", &err_str, session_time);
+                                        let owner = acc.user_id.as_deref().map(|uid| format!(" <@{}>", uid)).unwrap_or_default();
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("🚫 **{}** is banned or suspended.{} It is quarantined and won't be run again.", acc.name, owner)).await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("🚫 **[CRITICAL] Automation: {} is banned or suspended.**{} Quarantined; daily resets skip it until its status is changed.", acc.name, owner), source_channel).await;
+                                    },
+                                    Outcome::VerificationRequired => {
+                                        report.failure(&acc.name, "verification_required", &err_str, session_time);
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("⚠️ **{}** hit a human verification prompt. Skipping it until someone logs in manually.", acc.name)).await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("⚠️ **[ACTION NEEDED] Automation: {} needs human verification.** Account skipped.", acc.name), source_channel).await;
+                                    },
+                                    Outcome::ClientOutdated => {
+                                        report.failure(&acc.name, "client_outdated", &err_str, session_time);
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, "⚠️ **The game asked for a client update.** The bot may need updating. Stopping queue.").await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("⚠️ **[CRITICAL] Automation: the game asked for a client update while running {}.** The protocol may have changed. Stopping queue.", acc.name), source_channel).await;
+                                        break;
+                                    },
+                                    Outcome::InvalidConfig => {
+                                        report.failure(&acc.name, "invalid_config", &err_str, session_time);
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("⚠️ **Configuration error:** {}. Stopping queue.", err_str)).await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("⚠️ **[CRITICAL] Automation: configuration error.** {}. Stopping queue.", err_str), source_channel).await;
+                                        break;
+                                    },
+                                    Outcome::LoginRequired => {
+                                        report.failure(&acc.name, "login_required", &err_str, session_time);
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, "⚠️ **CRITICAL: Session cookie expired!** Stopping queue.").await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), "⚠️ **[CRITICAL] Automation: Session cookie expired!** Stopping queue.".to_string(), source_channel).await;
+                                        break;
+                                    },
+                                    Outcome::RateLimited => {
+                                        report.failure(&acc.name, "rate_limited", &err_str, session_time);
+                                        let cooldown = session_settings.rate_limit_cooldown_secs;
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("[WARN] Rate limited on **{}**. Backing off for {}s.", acc.name, cooldown)).await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[WARN] Automation: Rate limited on **{}**. Backing off for {}s.", acc.name, cooldown), source_channel).await;
+                                        tokio::time::sleep(tokio::time::Duration::from_secs(cooldown)).await;
+                                    },
+                                    Outcome::ConnectionIssue => {
+                                        report.failure(&acc.name, "connection_issue", &err_str, session_time);
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("[WARN] Connection issue on **{}** (Reason: {}). Retrying in 5s...", acc.name, err_str)).await;
+                                        }
+                                        if session_settings.resume_on_reconnect {
+                                            if let Some(point) = client.resume_point() {
+                                                resume_points.insert(acc.name.clone(), point);
+                                            }
+                                        }
+                                        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                                    },
+                                    Outcome::Failed if session_settings.unknown_outcome == UnknownOutcomePolicy::Retry
+                                        && unknown_retries.get(&acc.name).copied().unwrap_or(0) < session_settings.unknown_outcome_retries => {
+                                        let tries = unknown_retries.entry(acc.name.clone()).or_insert(0);
+                                        *tries += 1;
+                                        report.failure(&acc.name, "unknown_retry", &err_str, session_time);
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("[WARN] Unrecognised error on **{}** ({}). Retrying ({}/{})...", acc.name, err_str, tries, session_settings.unknown_outcome_retries)).await;
+                                        }
+                                        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                                    },
+                                    Outcome::Failed => {
+                                        report.failure(&acc.name, "failed", &err_str, session_time);
+                                        println!("[DEBUG] Decision trace for {} (run {}, sid {}):", acc.name, client.run_id(), client.sid());
+                                        for line in format_trace(&client.trace()) {
+                                            println!("[DEBUG]   {}", line);
+                                        }
+                                        println!("[DEBUG] Final screen for {}:", acc.name);
+                                        for line in client.final_screen() {
+                                            println!("[DEBUG]   {}", line);
+                                        }
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, &format!("error: {}", err_str));
+                                        }
+                                        if let Some(chan) = source_channel {
+                                            let _ = chan.say(&http_clone, format!("[ERROR] **{}** failed: {}", acc.name, err_str)).await;
+                                        }
+                                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[ERROR] Automation: **{}** failed. Reason: {}", acc.name, err_str), source_channel).await;
+                                    },
                                 }
                             }
                         }
                     },
+                    Err(e) if e.to_string().contains("COOKIE_COMMAND_FAILED") => {
+                        release_abort(&aborts, &acc.name, &abort).await;
+                        report.failure(&acc.name, "cookie_command_failed", &e.to_string(), session_started.elapsed());
+                        {
+                            let mut db = db_clone.lock().await;
+                            let _ = db.update_status(&acc.name, "error: COOKIE_COMMAND_FAILED");
+                        }
+                        if let Some(chan) = source_channel {
+                            let _ = chan.say(&http_clone, format!("[ERROR] Could not get a cookie for **{}**: {}", acc.name, e)).await;
+                        }
+                        Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[ERROR] Automation: cookie command for **{}** failed. Reason: {}", acc.name, e), source_channel).await;
+                    },
                     Err(e) => {
+                        release_abort(&aborts, &acc.name, &abort).await;
+                        report.failure(&acc.name, "connect_failed", &e.to_string(), session_started.elapsed());
                         if let Some(chan) = source_channel {
                             let _ = chan.say(&http_clone, format!("[ERROR] Connection failed for **{}**: {}", acc.name, e)).await;
                         }
-                        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                        // Reconnecting straight into a throttle only extends it.
+                        let delay = if e.to_string().contains("RATE_LIMITED") { session_settings.rate_limit_cooldown_secs } else { 5 };
+                        tokio::time::sleep(tokio::time::Duration::from_secs(delay)).await;
                     }
                 }
                 // 30s delay between accounts to prevent server overload / "session already running"
@@ -235,12 +687,47 @@ impl Handler {
             if let Some(chan) = source_channel {
                 let _ = chan.say(&http_clone, "[INFO] Queue Manager: Processing finished.").await;
             }
+
+            let report = report.finish();
+            if report.sessions > 0 {
+                for line in report.summary().replace("**", "").lines() {
+                    println!("[REPORT] {}", line);
+                }
+                if let Ok(json) = serde_json::to_string(&report) {
+                    println!("[REPORT] {}", json);
+                }
+                if let Some(chan) = source_channel {
+                    let _ = chan.say(&http_clone, report.summary()).await;
+                }
+                Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), report.summary(), source_channel).await;
+
+                let (webhook, detail) = {
+                    let db = db_clone.lock().await;
+                    (db.data.settings.summary_webhook_url.clone().filter(|u| !u.is_empty()), db.data.settings.summary_webhook_detail)
+                };
+                if let Some(url) = webhook {
+                    let payload = DiscordSummaryReporter { include_accounts: detail }.payload(&report);
+                    match reqwest::Client::new().post(&url).json(&payload).send().await {
+                        Ok(resp) if resp.status().is_success() => println!("[INFO] Queue report sent to the summary webhook."),
+                        Ok(resp) => println!("[WARN] Summary webhook answered HTTP {}.", resp.status()),
+                        Err(e) => println!("[WARN] Could not reach the summary webhook: {}", e),
+                    }
+                }
+            }
         });
+        true
     }
 
-    async fn process_handout_queue(&self, ctx: Context, source_channel: Option<ChannelId>) {
+    async fn process_handout_queue(&self, ctx: Context, group_filter: Option<String>, source_channel: Option<ChannelId>) {
+        if self.draining.load(Ordering::Relaxed) {
+            println!("[WARN] Shutting down. Not starting the handout queue.");
+            return;
+        }
         let db_clone = Arc::clone(&self.db);
         let processing_clone = Arc::clone(&self.is_processing);
+        let paused = Arc::clone(&self.paused);
+        let output = self.output.clone();
+        let aborts = Arc::clone(&self.aborts);
         let http_clone = ctx.http.clone();
 
         tokio::spawn(async move {
@@ -261,7 +748,11 @@ impl Handler {
 
             let accounts = {
                 let db = db_clone.lock().await;
-                db.get_handout_accounts()
+                let mut accounts = db.get_handout_accounts();
+                if let Some(group) = &group_filter {
+                    accounts.retain(|a| a.in_group(group));
+                }
+                accounts
             };
 
             for acc in accounts {
@@ -270,20 +761,45 @@ impl Handler {
                     if !*is_proc { break; }
                 }
                 
-                let cookie = {
+                let (cookie, session_settings, allow_duplicates) = {
                     let db = db_clone.lock().await;
-                    db.data.settings.cookies.clone().unwrap_or_default()
+                    (db.data.settings.cookies.clone().unwrap_or_default(), db.data.settings.session.clone(), db.data.settings.allow_duplicate_sessions)
+                };
+                if !acc.has_cookie_source(&cookie) { break; }
+
+                if session_settings.is_placeholder_code(&acc.decrypt_code()) {
+                    if let Some(chan) = source_channel {
+                        let _ = chan.say(&http_clone, format!("[ERROR] Handout **{}** has a placeholder restore code. Skipping it.", acc.name)).await;
+                    }
+                    continue;
+                }
+
+                let abort = match register_abort(&aborts, &acc.name, allow_duplicates).await {
+                    Ok(flag) => flag,
+                    Err(_) => {
+                        if let Some(chan) = source_channel {
+                            let _ = chan.say(&http_clone, format!("[WARN] Handout **{}** is already running in another session. Skipping it.", acc.name)).await;
+                        }
+                        continue;
+                    }
                 };
-                if cookie.is_empty() { break; }
 
                 if let Some(chan) = source_channel {
                      let _ = chan.say(&http_clone, format!("[INFO] Handout: Processing **{}**...", acc.name)).await;
                 }
 
-                match EvertextClient::connect(&cookie).await {
+                let session_started = std::time::Instant::now();
+                match EvertextClient::connect_account(&acc, &cookie, &session_settings).await {
                     Ok(mut client) => {
+                         client.set_pause_flag(Arc::clone(&paused));
+                         client.set_abort_flag(Arc::clone(&abort));
+                         forward_output(&client, &acc.name, &output);
                          let decrypted_code = acc.decrypt_code();
-                         match client.run_loop(&acc, &decrypted_code, RunMode::Handout).await {
+                         let result = client.run_loop(&acc, &decrypted_code, RunMode::Handout).await;
+                         release_abort(&aborts, &acc.name, &abort).await;
+                         log_result(&db_clone, &acc.name, &client, &result, session_started.elapsed()).await;
+                         record_server_use(&db_clone, &acc.name, &client).await;
+                         match result {
                              Ok(_) => {
                                  if let Some(chan) = source_channel {
                                      let _ = chan.say(&http_clone, format!("[SUCCESS] Handout **{}** completed.", acc.name)).await;
@@ -291,19 +807,41 @@ impl Handler {
                              },
                              Err(e) => {
                                  let err_str = e.to_string();
-                                 if err_str.contains("SESSION_COMPLETE") {
-                                      if let Some(chan) = source_channel {
-                                         let _ = chan.say(&http_clone, format!("[SUCCESS] Handout **{}** completed.", acc.name)).await;
-                                     }
-                                 } else {
-                                     if let Some(chan) = source_channel {
-                                         let _ = chan.say(&http_clone, format!("[ERROR] Handout **{}** failed: {}", acc.name, err_str)).await;
-                                     }
+                                 let outcome = classify(&err_str);
+                                 if let Some(status) = outcome.hold_status() {
+                                     let mut db = db_clone.lock().await;
+                                     let _ = db.update_status(&acc.name, status);
+                                 }
+                                 match outcome {
+                                     Outcome::Completed => {
+                                         if let Some(chan) = source_channel {
+                                             let _ = chan.say(&http_clone, format!("[SUCCESS] Handout **{}** completed.", acc.name)).await;
+                                         }
+                                     },
+                                     Outcome::EventUnavailable => {
+                                         if let Some(chan) = source_channel {
+                                             let _ = chan.say(&http_clone, format!("[INFO] Event not available for **{}**. Skipped.", acc.name)).await;
+                                         }
+                                     },
+                                     Outcome::CodeAlreadyUsed => {
+                                         if let Some(chan) = source_channel {
+                                             let _ = chan.say(&http_clone, format!("[INFO] **{}**'s restore code was already used today. Skipped.", acc.name)).await;
+                                         }
+                                     },
+                                     Outcome::Banned => {
+                                         Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("🚫 **[CRITICAL] Handout: {} is banned or suspended.** Quarantined.", acc.name), source_channel).await;
+                                     },
+                                     _ => {
+                                         if let Some(chan) = source_channel {
+                                             let _ = chan.say(&http_clone, format!("[ERROR] Handout **{}** failed: {}", acc.name, err_str)).await;
+                                         }
+                                     },
                                  }
                              }
                          }
                     },
                     Err(e) => {
+                         release_abort(&aborts, &acc.name, &abort).await;
                          if let Some(chan) = source_channel {
                              let _ = chan.say(&http_clone, format!("[ERROR] Connection failed for **{}**: {}", acc.name, e)).await;
                          }
@@ -348,9 +886,17 @@ impl EventHandler for Handler {
                 .description("Force run automation. Use 'all' to run all your accounts.")
                 .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name or 'all'").required(false)),
             CreateCommand::new("force_run_all")
-                .description("[ADMIN] Run all accounts in the system"),
+                .description("[ADMIN] Run all accounts in the system")
+                .add_option(CreateCommandOption::new(CommandOptionType::String, "group", "Only run accounts in this group").required(false)),
             CreateCommand::new("force_stop_all")
                 .description("[ADMIN] Stop all running processes"),
+            CreateCommand::new("abort_account")
+                .description("[ADMIN] Stop one running account's session, leaving the rest of the queue running")
+                .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true)),
+            CreateCommand::new("pause_automation")
+                .description("[ADMIN] Hold game commands while keeping sessions connected"),
+            CreateCommand::new("resume_automation")
+                .description("[ADMIN] Resume sending game commands"),
             CreateCommand::new("mute_bot")
                 .description("[ADMIN] Mute automatic bot messages"),
             CreateCommand::new("unmute_bot")
@@ -381,15 +927,21 @@ impl EventHandler for Handler {
             CreateCommand::new("reset_status")
                 .description("[ADMIN] Reset all accounts to 'pending'"),
             CreateCommand::new("run_handout")
-                .description("[ADMIN] Run Handout routine for enabled accounts"),
+                .description("[ADMIN] Run Handout routine for enabled accounts")
+                .add_option(CreateCommandOption::new(CommandOptionType::String, "group", "Only run accounts in this group").required(false)),
+            CreateCommand::new("set_group")
+                .description("[ADMIN] Put an account in a group (leave group empty to clear)")
+                .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true))
+                .add_option(CreateCommandOption::new(CommandOptionType::String, "group", "Group name, e.g. main or alts").required(false)),
         ]).await;
 
         println!("[INFO] Discord: Slash commands registered successfully");
 
+        control::spawn(self.clone(), ctx.clone());
+
         // Start Scheduler
-        let db_clone = Arc::clone(&self.db);
+        let scheduler = self.clone();
         let ctx_clone = ctx.clone();
-        let is_processing_clone = Arc::clone(&self.is_processing);
         
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
@@ -399,31 +951,75 @@ impl EventHandler for Handler {
                 if now.hour() == 22 && now.minute() == 0 {
                     println!("[INFO] Scheduler: Daily reset triggered at {}", now);
                     {
-                        let mut db = db_clone.lock().await;
-                        let _ = db.reset_all_statuses();
+                        let mut db = scheduler.db.lock().await;
+                        let _ = db.reset_daily_statuses(Utc::now());
                     }
                     
                     // Trigger queue for all accounts
-                     let db_c = Arc::clone(&db_clone);
-                     let proc_c = Arc::clone(&is_processing_clone);
+                     let h = scheduler.clone();
                      let ctx_c = ctx_clone.clone();
 
                      tokio::spawn(async move {
-                         let h = Handler { db: db_c, is_processing: proc_c };
-                         h.process_queue(ctx_c, None, None).await;
+                         h.process_queue(ctx_c, None, None, None).await;
                      });
                 }
                 
+                // Interval runs: at the start of each period, re-queue what hasn't finished in it.
+                let run_interval = {
+                    let db = scheduler.db.lock().await;
+                    db.data.settings.run_interval_hours.filter(|h| *h > 0)
+                };
+                if let Some(hours) = run_interval {
+                    let period = hours as i64 * 3600;
+                    let ts = Utc::now().timestamp();
+                    if ts % period < 60 {
+                        let busy = *scheduler.is_processing.lock().await;
+                        if busy || scheduler.paused.load(Ordering::Relaxed) {
+                            println!("[INFO] Scheduler: Interval run skipped, the queue is {}.", if busy { "still running" } else { "paused" });
+                        } else if let Some(period_start) = chrono::DateTime::from_timestamp(ts - ts % period, 0) {
+                            println!("[INFO] Scheduler: {}h interval run triggered at {}", hours, now);
+                            {
+                                let mut db = scheduler.db.lock().await;
+                                let _ = db.reset_for_period(period_start);
+                            }
+                            let h = scheduler.clone();
+                            let ctx_c = ctx_clone.clone();
+                            tokio::spawn(async move {
+                                h.process_queue(ctx_c, None, None, None).await;
+                            });
+                        }
+                    }
+                }
+
+                // Accounts with a dailyWindow are skipped outside it, so start the
+                // queue again whenever one of their windows opens.
+                let window_opened = {
+                    let db = scheduler.db.lock().await;
+                    let utc_now = Utc::now();
+                    db.data.accounts.iter().any(|a| {
+                        a.status != "done" && a.daily_window().is_some_and(|(start, _)| {
+                            let local = utc_now.with_timezone(&a.tz());
+                            local.hour() == start.hour() && local.minute() == start.minute()
+                        })
+                    })
+                };
+                if window_opened {
+                    println!("[INFO] Scheduler: Account window opened at {}", now);
+                    let h = scheduler.clone();
+                    let ctx_c = ctx_clone.clone();
+                    tokio::spawn(async move {
+                        h.process_queue(ctx_c, None, None, None).await;
+                    });
+                }
+
                 // Handout Routine (18:00 Local)
                 if now.hour() == 18 && now.minute() == 0 {
                     println!("[INFO] Scheduler: Handout routine triggered at {}", now);
-                     let db_c = Arc::clone(&db_clone);
-                     let proc_c = Arc::clone(&is_processing_clone);
+                     let h = scheduler.clone();
                      let ctx_c = ctx_clone.clone();
 
                      tokio::spawn(async move {
-                         let h = Handler { db: db_c, is_processing: proc_c };
-                         h.process_handout_queue(ctx_c, None).await;
+                         h.process_handout_queue(ctx_c, None, None).await;
                      });
                 }
             }
@@ -434,7 +1030,7 @@ impl EventHandler for Handler {
         if let Interaction::Command(command) = interaction {
             let user_id = command.user.id.to_string();
             let mut content = "Processing...".to_string();
-            let mut extra_chunks: Vec<String> = Vec::new();
+            let extra_chunks: Vec<String> = Vec::new();
 
             match command.data.name.as_str() {
                 "list_accounts" => {
@@ -566,11 +1162,12 @@ impl EventHandler for Handler {
                             handout_enabled: false,
                             status: "pending".to_string(),
                             last_run: None,
+                            ..Default::default()
                         };
                         let _ = db.add_account(new_acc);
                     }
                     content = format!("Successfully added account **{}**.", name);
-                    self.process_queue(ctx.clone(), Some(user_id), Some(command.channel_id)).await;
+                    self.process_queue(ctx.clone(), Some(user_id), None, Some(command.channel_id)).await;
                 },
                 "remove_account" => {
                     let mut db = self.db.lock().await;
@@ -594,18 +1191,23 @@ impl EventHandler for Handler {
                     
                     if target_name.to_lowercase() == "all" {
                         // Run all for THIS user
-                        self.process_queue(ctx.clone(), Some(user_id), Some(command.channel_id)).await;
+                        self.process_queue(ctx.clone(), Some(user_id), None, Some(command.channel_id)).await;
                         content = "Queued all your accounts for execution.".to_string();
+                    } else if self.draining.load(Ordering::Relaxed) {
+                        content = "The bot is shutting down. Not starting new sessions.".to_string();
                     } else {
                         // Start single
                         let db_clone = Arc::clone(&self.db);
                         let processing_clone = Arc::clone(&self.is_processing);
+                        let paused = Arc::clone(&self.paused);
+                        let output = self.output.clone();
+                        let aborts = Arc::clone(&self.aborts);
                         let http_clone = ctx.http.clone();
                         let channel_id = command.channel_id;
                         let n_owned = target_name.to_string();
                         
                          tokio::spawn(async move {
-                            let (cookie, acc) = {
+                            let (cookie, session_settings, allow_duplicates, acc) = {
                                 let mut is_proc = processing_clone.lock().await;
                                 if *is_proc {
                                     let _ = channel_id.say(&http_clone, "[WARN] Already in progress.").await;
@@ -614,19 +1216,34 @@ impl EventHandler for Handler {
                                 *is_proc = true;
                                 
                                 let db = db_clone.lock().await;
-                                (db.data.settings.cookies.clone().unwrap_or_default(), 
+                                (db.data.settings.cookies.clone().unwrap_or_default(),
+                                 db.data.settings.session.clone(),
+                                 db.data.settings.allow_duplicate_sessions,
                                  db.data.accounts.iter().find(|a| a.name == n_owned).cloned())
                             };
                             
                             if let Some(acc) = acc {
-                                if cookie.is_empty() {
+                                if !acc.has_cookie_source(&cookie) {
                                     let _ = channel_id.say(&http_clone, "[ERROR] No cookies set.").await;
-                                } else {
+                                } else if session_settings.is_placeholder_code(&acc.decrypt_code()) {
+                                    let _ = channel_id.say(&http_clone, format!("[ERROR] **{}** has a placeholder restore code (PLACEHOLDER_CODE).", acc.name)).await;
+                                } else if let Ok(abort) = register_abort(&aborts, &acc.name, allow_duplicates).await {
                                     let _ = channel_id.say(&http_clone, format!("[INFO] Force running **{}**...", acc.name)).await;
-                                    match EvertextClient::connect(&cookie).await {
+                                    let session_started = std::time::Instant::now();
+                                    match EvertextClient::connect_account(&acc, &cookie, &session_settings).await {
                                         Ok(mut client) => {
+                                            client.set_pause_flag(paused);
+                                            client.set_abort_flag(Arc::clone(&abort));
+                                            forward_output(&client, &acc.name, &output);
+                                            if let Some(policy) = loop_window_policy(&acc) {
+                                                client.set_continue_policy(policy);
+                                            }
                                             let decrypted_code = acc.decrypt_code();
-                                            match client.run_loop(&acc, &decrypted_code, RunMode::Daily).await {
+                                            let result = client.run_loop(&acc, &decrypted_code, acc.run_mode()).await;
+                                            release_abort(&aborts, &acc.name, &abort).await;
+                                            log_result(&db_clone, &acc.name, &client, &result, session_started.elapsed()).await;
+                                            record_server_use(&db_clone, &acc.name, &client).await;
+                                            match result {
                                                 Ok(_) => {
                                                     let mut db = db_clone.lock().await;
                                                     let _ = db.update_status(&acc.name, "done");
@@ -634,20 +1251,44 @@ impl EventHandler for Handler {
                                                 },
                                                 Err(e) => {
                                                     let err_str = e.to_string();
-                                                    if err_str.contains("SESSION_COMPLETE") {
+                                                    let outcome = classify(&err_str);
+                                                    if let Some(status) = outcome.hold_status() {
                                                         let mut db = db_clone.lock().await;
-                                                        let _ = db.update_status(&acc.name, "done");
-                                                        let _ = channel_id.say(&http_clone, format!("[SUCCESS] **{}** finished.", acc.name)).await;
-                                                    } else {
-                                                        let _ = channel_id.say(&http_clone, format!("[ERROR] **{}** failed: {}", acc.name, err_str)).await;
+                                                        let _ = db.update_status(&acc.name, status);
+                                                    }
+                                                    match outcome {
+                                                        Outcome::Completed => {
+                                                            let mut db = db_clone.lock().await;
+                                                            let _ = db.update_status(&acc.name, "done");
+                                                            let _ = channel_id.say(&http_clone, format!("[SUCCESS] **{}** finished.", acc.name)).await;
+                                                        },
+                                                        Outcome::EventUnavailable => {
+                                                            let mut db = db_clone.lock().await;
+                                                            let _ = db.update_status(&acc.name, "done");
+                                                            let _ = channel_id.say(&http_clone, format!("[INFO] Event not available for **{}**. Marked as done.", acc.name)).await;
+                                                        },
+                                                        Outcome::CodeAlreadyUsed => {
+                                                            let mut db = db_clone.lock().await;
+                                                            let _ = db.update_status(&acc.name, "done");
+                                                            let _ = channel_id.say(&http_clone, format!("[INFO] **{}**'s restore code was already used today. Marked as done.", acc.name)).await;
+                                                        },
+                                                        Outcome::Banned => {
+                                                            let _ = channel_id.say(&http_clone, format!("🚫 **{}** is banned or suspended. It is quarantined and won't be run again.", acc.name)).await;
+                                                        },
+                                                        _ => {
+                                                            let _ = channel_id.say(&http_clone, format!("[ERROR] **{}** failed: {}", acc.name, err_str)).await;
+                                                        },
                                                     }
                                                 }
                                             }
                                         },
                                         Err(e) => {
+                                            release_abort(&aborts, &acc.name, &abort).await;
                                             let _ = channel_id.say(&http_clone, format!("[ERROR] Connection failed: {}", e)).await;
                                         }
                                     }
+                                } else {
+                                    let _ = channel_id.say(&http_clone, format!("[ERROR] **{}** is already running in another session (ALREADY_RUNNING).", acc.name)).await;
                                 }
                             } else {
                                 let _ = channel_id.say(&http_clone, format!("[ERROR] Account **{}** not found.", n_owned)).await;
@@ -663,8 +1304,20 @@ impl EventHandler for Handler {
                     if !self.is_admin(&ctx, &command).await {
                         content = "Admin permissions required.".to_string();
                     } else {
-                        self.process_queue(ctx.clone(), None, Some(command.channel_id)).await;
-                        content = "Starting ALL pending accounts...".to_string();
+                        let group = command.data.options.iter().find(|o| o.name == "group").and_then(|o| o.value.as_str()).map(|g| g.to_string());
+                        let group_known = match &group {
+                            Some(g) => self.db.lock().await.has_group(g),
+                            None => true,
+                        };
+                        if !group_known {
+                            content = format!("❌ No accounts in group **{}**.", group.unwrap_or_default());
+                        } else {
+                            content = match &group {
+                                Some(g) => format!("Starting pending accounts in group **{}**...", g),
+                                None => "Starting ALL pending accounts...".to_string(),
+                            };
+                            self.process_queue(ctx.clone(), None, group, Some(command.channel_id)).await;
+                        }
                     }
                 },
                 "force_stop_all" => {
@@ -676,6 +1329,36 @@ impl EventHandler for Handler {
                         content = "Queue processing halted.".to_string();
                     }
                 },
+                "abort_account" => {
+                    if !self.is_admin(&ctx, &command).await {
+                        content = "Admin permissions required.".to_string();
+                    } else {
+                        let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("");
+                        match self.aborts.lock().await.get(name) {
+                            Some(flag) => {
+                                flag.store(true, Ordering::Relaxed);
+                                content = format!("Aborting **{}**. It will send 'exit' and the queue moves on.", name);
+                            },
+                            None => content = format!("**{}** is not running.", name),
+                        }
+                    }
+                },
+                "pause_automation" => {
+                    if !self.is_admin(&ctx, &command).await {
+                        content = "Admin permissions required.".to_string();
+                    } else {
+                        self.paused.store(true, Ordering::Relaxed);
+                        content = "⏸️ Automation paused. Sessions stay connected; commands are held per `pausePolicy`.".to_string();
+                    }
+                },
+                "resume_automation" => {
+                    if !self.is_admin(&ctx, &command).await {
+                        content = "Admin permissions required.".to_string();
+                    } else {
+                        self.paused.store(false, Ordering::Relaxed);
+                        content = "▶️ Automation resumed.".to_string();
+                    }
+                },
 // ... Inside interaction_create match block
                 "add_admin" => {
                     // Start of admin check
@@ -806,6 +1489,20 @@ impl EventHandler for Handler {
                          }
                     }
                 },
+                "set_group" => {
+                    if !self.is_admin(&ctx, &command).await {
+                         content = "Admin permissions required.".to_string();
+                    } else {
+                         let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("");
+                         let group = command.data.options.iter().find(|o| o.name == "group").and_then(|o| o.value.as_str());
+                         let mut db = self.db.lock().await;
+                         match (db.set_group(name, group), group) {
+                             (Ok(_), Some(g)) => content = format!("**{}** is now in group **{}**.", name, g),
+                             (Ok(_), None) => content = format!("Removed **{}** from its group.", name),
+                             (Err(_), _) => content = format!("Account **{}** not found.", name),
+                         }
+                    }
+                },
                 "reset_status" => {
                     if !self.is_admin(&ctx, &command).await {
                          content = "Admin permissions required.".to_string();
@@ -882,9 +1579,19 @@ impl EventHandler for Handler {
                     } else {
                          // Time restriction check (18:00 - 19:00 Jakarta)
                          let now = Utc::now().with_timezone(&Jakarta);
-                         if now.hour() == 18 {
-                             self.process_handout_queue(ctx.clone(), Some(command.channel_id)).await;
-                             content = "Starting Handout routine for all enabled accounts... Check logs.".to_string();
+                         let group = command.data.options.iter().find(|o| o.name == "group").and_then(|o| o.value.as_str()).map(|g| g.to_string());
+                         let group_known = match &group {
+                             Some(g) => self.db.lock().await.has_group(g),
+                             None => true,
+                         };
+                         if !group_known {
+                             content = format!("❌ No accounts in group **{}**.", group.unwrap_or_default());
+                         } else if now.hour() == 18 {
+                             content = match &group {
+                                 Some(g) => format!("Starting Handout routine for enabled accounts in group **{}**... Check logs.", g),
+                                 None => "Starting Handout routine for all enabled accounts... Check logs.".to_string(),
+                             };
+                             self.process_handout_queue(ctx.clone(), group, Some(command.channel_id)).await;
                          } else {
                              content = format!("❌ **Handout command is restricted to 18:00 - 19:00 Jakarta time.** (Current time: {:02}:{:02})", now.hour(), now.minute());
                          }
@@ -906,6 +1613,35 @@ impl EventHandler for Handler {
 
 #[tokio::main]
 async fn main() {
+    dotenv::dotenv().ok();
+    env_logger::init();
+
+    let cli = Cli::parse();
+    let result = match cli.command {
+        None => { run_bot().await; Ok(()) },
+        Some(Commands::Run { config, account, mode, script }) => {
+            if let Some(path) = config {
+                std::env::set_var("DATABASE_PATH", path);
+            }
+            match account {
+                Some(name) => cli::run_account(&name, mode.unwrap_or(RunMode::Daily), script).await,
+                None => { run_bot().await; Ok(()) },
+            }
+        },
+        Some(Commands::Diagnose { account }) => cli::diagnose(&account).await,
+        Some(Commands::Replay { file, mode, account, script }) => cli::replay(&file, mode, account.as_deref(), script),
+        Some(Commands::Diagram { config }) => cli::diagram(config.as_deref()),
+    };
+
+    if let Err(e) = result {
+        println!("[ERROR] {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run_bot() {
+    let output_hub = broadcast::channel::<String>(1024).0;
+
     // --- Zeabur Health Check Server (Immediate Start) ---
     tokio::spawn(async move {
         use warp::Filter;
@@ -915,9 +1651,6 @@ async fn main() {
         warp::serve(health_route).run(([0, 0, 0, 0], port)).await;
     });
 
-    dotenv::dotenv().ok();
-    env_logger::init();
-    
     let token = std::env::var("DISCORD_TOKEN").unwrap_or_default();
     if token.is_empty() {
         println!("[CRITICAL] DISCORD_TOKEN is missing! Keeping health check alive for debugging.");
@@ -936,7 +1669,12 @@ async fn main() {
     let handler = Handler {
         db: database,
         is_processing: Arc::new(Mutex::new(false)),
+        paused: Arc::new(AtomicBool::new(false)),
+        output: output_hub,
+        aborts: Arc::new(Mutex::new(HashMap::new())),
+        draining: Arc::new(AtomicBool::new(false)),
     };
+    tokio::spawn(drain_on_shutdown(handler.clone()));
 
     let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::DIRECT_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
 
@@ -0,0 +1,1500 @@
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use serde::Serialize;
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+use std::sync::OnceLock;
+
+use crate::db::{Account, CompletionRule, EventEnvelope, PromptReply, ResumeReprint, ServerStrategy, SessionSettings};
+use super::socket::RunMode;
+
+#[allow(dead_code)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum GameState {
+    Connected,
+    WaitingForCommandPrompt,
+    SentD,
+    WaitingForRestorePrompt,
+    SentCode,
+    WaitingForServerList,
+    ServerSelected,
+    WaitingProcedure,
+    RapidFire,
+    Finished,
+}
+
+const COMMAND_PROMPT: &str = "Enter Command to use";
+const RESTORE_PROMPT: &str = "Enter Restore code";
+const SERVER_PROMPT: &str = "Which acc u want to Login";
+const MANA_PROMPT: &str = "Press y to spend mana on event stages";
+const REFILL_PROMPT: &str = "DO U WANT TO REFILL MANA";
+const NEXT_EVENT_PROMPT: &str = "next: Go to the next event";
+// Matched case-insensitively.
+const INVALID_COMMAND: &str = "invalid command";
+const INCORRECT_CODE: &str = "incorrect restore code";
+const SERVER_FULL: &str = "maximum limit of restore";
+pub const MORE_COMMANDS_PROMPT: &str = "Press y to perform more commands";
+
+/// One fixed prompt the session answers, for documentation (`diagram`).
+pub struct PromptRule {
+    pub prompt: &'static str,
+    pub reply: &'static str,
+    pub next: Option<GameState>,
+}
+
+/// The fixed prompts in the order a daily run meets them. `handle_output`
+/// matches the same constants, so this table names exactly what it reacts to.
+pub const PROMPT_RULES: &[PromptRule] = &[
+    PromptRule { prompt: COMMAND_PROMPT, reply: "d (daily) or ho (handout)", next: Some(GameState::SentD) },
+    PromptRule { prompt: RESTORE_PROMPT, reply: "restore code", next: Some(GameState::SentCode) },
+    PromptRule { prompt: SERVER_PROMPT, reply: "index of the target server", next: Some(GameState::ServerSelected) },
+    PromptRule { prompt: MANA_PROMPT, reply: "mana response", next: Some(GameState::WaitingProcedure) },
+    PromptRule { prompt: NEXT_EVENT_PROMPT, reply: "auto, then exit", next: Some(GameState::RapidFire) },
+    PromptRule { prompt: MORE_COMMANDS_PROMPT, reply: "end session if work is done, else y", next: Some(GameState::Finished) },
+    PromptRule { prompt: REFILL_PROMPT, reply: "y (n if refillMana is false)", next: None },
+];
+
+/// Renders `PROMPT_RULES` plus the prompts configured in `settings` as a
+/// Mermaid state diagram. Rules that don't move the state are listed in a note.
+pub fn mermaid_diagram(settings: &SessionSettings) -> String {
+    let mut out = String::from("stateDiagram-v2\n");
+    let mut state = GameState::Connected;
+    out.push_str(&format!("    [*] --> {:?}\n", state));
+    for rule in PROMPT_RULES {
+        if let Some(next) = rule.next {
+            out.push_str(&format!("    {:?} --> {:?}: \"{}\" / {}\n", state, next, rule.prompt, rule.reply));
+            state = next;
+        }
+    }
+    out.push_str(&format!("    {:?} --> [*]\n", state));
+
+    let mut any_state: Vec<String> = PROMPT_RULES.iter()
+        .filter(|rule| rule.next.is_none())
+        .map(|rule| format!("\"{}\" / {}", rule.prompt, rule.reply))
+        .collect();
+    if let Some(prompt) = settings.potion_prompt.as_deref().filter(|p| !p.is_empty()) {
+        any_state.push(format!("\"{}\" / potion quantity", prompt));
+    }
+    for choice in &settings.menu_choices {
+        any_state.push(format!("\"{}\" / index of '{}'", choice.prompt, choice.label));
+    }
+    for sequence in &settings.confirm_sequences {
+        let steps: Vec<String> = sequence.steps.iter().map(|step| format!("\"{}\" / {}", step.prompt, step.reply)).collect();
+        any_state.push(format!("{} (in order)", steps.join(", then ")));
+    }
+    for reply in &settings.prompt_replies {
+        any_state.push(format!("\"{}\" / {} (when nothing else answered)", reply.prompt, reply.reply));
+    }
+    if !settings.confirm_pattern.is_empty() {
+        any_state.push(format!("/{}/ / {} (when nothing else answered)", settings.confirm_pattern, settings.confirm_answer));
+    }
+    if let (false, Some(min)) = (settings.balance_pattern.is_empty(), settings.min_balance) {
+        any_state.push(format!("/{}/ below {} / n to mana and refill", settings.balance_pattern, min));
+    }
+    for pattern in &settings.verification_patterns {
+        any_state.push(format!("\"{}\" / abort: HUMAN_VERIFICATION_REQUIRED", pattern));
+    }
+    for pattern in &settings.banned_patterns {
+        any_state.push(format!("\"{}\" / abort: ACCOUNT_BANNED", pattern));
+    }
+    for pattern in &settings.maintenance_patterns {
+        any_state.push(format!("\"{}\" / abort: SERVER_MAINTENANCE", pattern));
+    }
+    for pattern in &settings.client_outdated_patterns {
+        any_state.push(format!("\"{}\" / abort: CLIENT_OUTDATED", pattern));
+    }
+    for pattern in &settings.premium_spend_patterns {
+        any_state.push(format!("\"{}\" / {} (unless allowGemSpend)", pattern, settings.premium_spend_decline));
+    }
+    for pattern in &settings.event_unavailable_patterns {
+        any_state.push(format!("\"{}\" / end: EVENT_UNAVAILABLE", pattern));
+    }
+    for pattern in &settings.code_used_patterns {
+        any_state.push(format!("\"{}\" / end: CODE_ALREADY_USED", pattern));
+    }
+    for pattern in &settings.rate_limit_patterns {
+        any_state.push(format!("\"{}\" / abort: RATE_LIMITED", pattern));
+    }
+    out.push_str(&format!("    note right of {:?}\n", GameState::Connected));
+    out.push_str("        Answered in any state:\n");
+    for line in any_state {
+        out.push_str(&format!("        {}\n", line));
+    }
+    out.push_str("    end note\n");
+    out
+}
+
+/// Maximum number of entries kept in a session trace; older entries are dropped first.
+const TRACE_LIMIT: usize = 200;
+
+/// Characters of output history kept for prompt matching.
+const HISTORY_LIMIT: usize = 15000;
+/// Text before the newest frame that trimming always keeps, so a prompt split across frames still matches.
+const TRIM_OVERLAP: usize = 256;
+
+/// Renders a trace as `+<ms> <entry>` lines relative to its first entry.
+pub fn format_trace(trace: &[(Instant, String)]) -> Vec<String> {
+    let Some((start, _)) = trace.first() else {
+        return Vec::new();
+    };
+    trace.iter()
+        .map(|(at, entry)| format!("+{}ms {}", at.duration_since(*start).as_millis(), entry))
+        .collect()
+}
+
+/// The restore codes a session may type, so logs and transcripts can hide them.
+#[derive(Debug, Clone, Default)]
+pub struct CodeMask {
+    codes: Vec<String>,
+    chunk_size: Option<usize>,
+}
+
+impl CodeMask {
+    /// Whether `cmd` is one of the codes, or one of the `codeChunkSize` pieces of one.
+    pub fn hides(&self, cmd: &str) -> bool {
+        self.codes.iter().any(|code| {
+            let chars: Vec<char> = code.chars().collect();
+            cmd == code || self.chunk_size.filter(|size| *size > 0)
+                .is_some_and(|size| chars.chunks(size).any(|chunk| chunk.iter().collect::<String>() == cmd))
+        })
+    }
+}
+
+/// Decides at "Press y to perform more commands" whether to keep playing ('y')
+/// or end the session. Replaces the keyword heuristic when set.
+pub type ContinuePolicy = Box<dyn Fn() -> bool + Send + Sync>;
+
+/// Custom decision logic consulted on every output frame that no built-in rule
+/// answered. `output` is that frame, already appended to `history`; the returned
+/// strings are sent as commands and count towards `maxCommands`.
+pub trait PromptMatcher {
+    fn on_output(&mut self, history: &str, output: &str, state: &GameState) -> Vec<String>;
+}
+
+/// The default `PromptMatcher`: answers each `promptReplies` prompt in the frame.
+pub struct PromptReplies {
+    replies: Vec<PromptReply>,
+}
+
+impl PromptReplies {
+    /// None when `promptReplies` is empty.
+    pub fn from_settings(settings: &SessionSettings) -> Option<Self> {
+        (!settings.prompt_replies.is_empty()).then(|| Self { replies: settings.prompt_replies.clone() })
+    }
+}
+
+impl PromptMatcher for PromptReplies {
+    fn on_output(&mut self, _history: &str, output: &str, _state: &GameState) -> Vec<String> {
+        self.replies.iter()
+            .filter(|r| output.contains(&r.prompt))
+            .map(|r| r.reply.clone())
+            .collect()
+    }
+}
+
+/// One `N--> ... (Server)` line of the login list.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServerEntry {
+    pub index: u32,
+    pub name: String,
+}
+
+/// Event name and data of a decoded `42` payload, laid out per `envelope`.
+/// Returns None when the payload doesn't have that shape.
+fn unwrap_event(event: &serde_json::Value, envelope: EventEnvelope) -> Option<(&str, Option<&serde_json::Value>)> {
+    match envelope {
+        EventEnvelope::Array => {
+            let event_array = event.as_array()?;
+            Some((event_array.first().and_then(|v| v.as_str()).unwrap_or(""), event_array.get(1)))
+        },
+        EventEnvelope::Object => {
+            let event_object = event.as_object()?;
+            Some((event_object.get("event").and_then(|v| v.as_str()).unwrap_or(""), event_object.get("data")))
+        },
+    }
+}
+
+/// Text of an output field, which is either a string or an array of lines.
+/// Array elements are joined with newlines; non-string elements are skipped.
+fn output_text(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(text) => Some(text.clone()),
+        serde_json::Value::Array(lines) => {
+            Some(lines.iter().filter_map(|line| line.as_str()).collect::<Vec<_>>().join("\n"))
+        },
+        _ => None,
+    }
+}
+
+/// Whether `word` occurs in `text` with no letter or digit directly before or after it.
+fn contains_word(text: &str, word: &str) -> bool {
+    !word.is_empty() && text.match_indices(word).any(|(at, _)| {
+        let before = text[..at].chars().next_back();
+        let after = text[at + word.len()..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
+
+/// One `N--> text` line of a numbered game menu.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MenuEntry {
+    pub index: u32,
+    pub label: String,
+}
+
+/// Parses the numbered menu entries out of `text`.
+///
+/// Each entry is `<index>--><text>`, with optional whitespace around the arrow;
+/// the label is the trimmed text up to the next entry or the end of the line, so
+/// several entries may share a line. Entries are returned in the order they appear.
+pub fn parse_menu(text: &str) -> Vec<MenuEntry> {
+    static MENU_ARROW: OnceLock<Option<Regex>> = OnceLock::new();
+    let Some(re) = MENU_ARROW.get_or_init(|| Regex::new(r"(\d+)[ \t]*-->").ok()) else {
+        return Vec::new();
+    };
+    let heads: Vec<_> = re.captures_iter(text).collect();
+    heads.iter().enumerate()
+        .filter_map(|(i, cap)| {
+            let index = cap[1].parse().ok()?;
+            let start = cap.get(0)?.end();
+            let end = heads.get(i + 1).and_then(|next| next.get(0)).map_or(text.len(), |next| next.start());
+            let label = text[start..end].split('\n').next().unwrap_or("");
+            Some(MenuEntry { index, label: label.trim().to_string() })
+        })
+        .collect()
+}
+
+/// Index of the first menu entry in `text` whose label contains `label`, ignoring case.
+pub fn menu_index(text: &str, label: &str) -> Option<u32> {
+    let label = label.to_lowercase();
+    parse_menu(text).into_iter().find(|entry| entry.label.to_lowercase().contains(&label)).map(|entry| entry.index)
+}
+
+/// Parses the "Which acc u want to Login" list out of `history`.
+///
+/// The server name is the first parenthesised group of a menu label, with
+/// nested parentheses kept intact, so `3 --> Hero (E-15 (Asia))` yields
+/// `E-15 (Asia)`. A label without parentheses is used whole, and an unclosed
+/// group runs to the end of the line.
+pub fn parse_server_list(history: &str) -> Vec<ServerEntry> {
+    parse_menu(history).into_iter()
+        .map(|entry| ServerEntry { index: entry.index, name: parenthesised_name(&entry.label) })
+        .collect()
+}
+
+/// Chooses one of the matching server entries according to `strategy`.
+/// `last_used` maps server names to RFC3339 timestamps; unused servers go first under `Lru`.
+pub fn pick_server(strategy: ServerStrategy, mut candidates: Vec<ServerEntry>, last_used: &HashMap<String, String>) -> Option<ServerEntry> {
+    if candidates.is_empty() {
+        return None;
+    }
+    let i = match strategy {
+        ServerStrategy::First => 0,
+        ServerStrategy::Random => rand::thread_rng().gen_range(0..candidates.len()),
+        ServerStrategy::Lru => candidates.iter()
+            .enumerate()
+            .min_by_key(|(_, entry)| last_used.get(&entry.name))
+            .map_or(0, |(i, _)| i),
+    };
+    Some(candidates.swap_remove(i))
+}
+
+fn parenthesised_name(text: &str) -> String {
+    let Some(open) = text.find('(') else {
+        return text.trim().to_string();
+    };
+    let mut depth = 0;
+    for (i, c) in text[open..].char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return text[open + 1..open + i].trim().to_string();
+                }
+            },
+            _ => {}
+        }
+    }
+    text[open + 1..].trim().to_string()
+}
+
+/// Picks the answer to the potion quantity prompt: `desired` (or everything when
+/// unset) capped at the last count `re` finds in `output`. Falls back to 1
+/// when no count is found, and never answers less than 1.
+pub fn potion_quantity(output: &str, re: &Regex, desired: Option<u32>) -> u32 {
+    let available = re.captures_iter(output)
+        .filter_map(|cap| cap.get(1)?.as_str().parse::<u32>().ok())
+        .last();
+    match available {
+        Some(available) => desired.map_or(available, |d| d.min(available)).max(1),
+        None => 1,
+    }
+}
+
+/// The restore code as it is typed. Surrounding whitespace (a trailing newline from
+/// a bad decrypt, say) is dropped. Fails with MISSING_CODE when nothing is left, and
+/// with INVALID_CODE_FORMAT when the code holds characters the terminal can't type.
+pub fn clean_code(code: &str) -> Result<&str, &'static str> {
+    let code = code.trim();
+    if code.is_empty() {
+        Err("MISSING_CODE")
+    } else if code.chars().any(char::is_control) {
+        Err("INVALID_CODE_FORMAT")
+    } else {
+        Ok(code)
+    }
+}
+
+/// Items gained during a session, read with `rewardPatterns`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct InventoryDelta {
+    pub items: BTreeMap<String, i64>,
+}
+
+impl InventoryDelta {
+    pub fn add(&mut self, item: &str, amount: i64) {
+        *self.items.entry(item.to_string()).or_insert(0) += amount;
+    }
+
+    pub fn merge(&mut self, other: &InventoryDelta) {
+        for (item, amount) in &other.items {
+            self.add(item, *amount);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// "3x potion, 2x scroll".
+    pub fn describe(&self) -> String {
+        self.items.iter().map(|(item, amount)| format!("{}x {}", amount, item)).collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// Where a session was when its connection dropped, used to pick up the
+/// same game on the next connection instead of restarting it.
+#[derive(Debug, Clone)]
+pub struct ResumePoint {
+    pub state: GameState,
+    last_command: String,
+    auto_sent: bool,
+    mana_hits: usize,
+    mode: RunMode,
+    pending_modes: Vec<RunMode>,
+}
+
+/// Prompt handling for one game session, kept apart from the socket so the
+/// same logic drives live runs and offline transcript replays.
+/// Commands decided while handling an event are queued and must be drained
+/// with `take_commands` by whoever owns the connection.
+pub struct Session {
+    account: Account,
+    code: String,
+    // The plain mode being played; `pending_modes` follow it in a sequence.
+    mode: RunMode,
+    pending_modes: VecDeque<RunMode>,
+    // Every mode of the run, for playing it again on `repeat`.
+    steps: Vec<RunMode>,
+    settings: SessionSettings,
+    state: GameState,
+    history: String,
+    // Per trigger text, the byte offset in `history` up to which it has been answered.
+    // Only occurrences past it fire again.
+    processed: HashMap<String, usize>,
+    auto_sent: bool,
+    mana_hits: usize,
+    last_command: Option<String>,
+    commands_sent: usize,
+    // Each queued command with the trigger that produced it.
+    outbox: Vec<(String, String)>,
+    // The last trigger noted while handling the current frame.
+    trigger: String,
+    trace: VecDeque<(Instant, String)>,
+    continue_policy: Option<ContinuePolicy>,
+    prompt_matcher: Option<Box<dyn PromptMatcher + Send + Sync>>,
+    unhandled_events: BTreeSet<String>,
+    confirm_re: Option<Regex>,
+    mask: CodeMask,
+    potion_count_re: Regex,
+    balance_re: Option<Regex>,
+    // Last balance `balancePattern` read from the output.
+    balance: Option<u64>,
+    // Set once a completion prompt found evidence that the run did its work.
+    work_done: bool,
+    reward_res: Vec<(String, Regex)>,
+    rewards: InventoryDelta,
+    completion: CompletionRule,
+    screen: VecDeque<String>,
+    // Set once the mode's fallback replaced its menu command after "Invalid Command".
+    menu_fallback: Option<String>,
+    // Next step of each `confirmSequences` entry.
+    confirm_steps: Vec<usize>,
+    code_chunked: bool,
+    // Account `codes` not tried yet; the next one replaces `code` when it is rejected.
+    spare_codes: VecDeque<String>,
+    // Delay the connection owner should leave between the commands it takes next.
+    pace: Option<Duration>,
+    selected_server: Option<ServerEntry>,
+    // 0 while logging into `targetServer`, n for the n-th entry of `fallbackServers`.
+    server_attempt: usize,
+    output_tx: Option<broadcast::Sender<String>>,
+}
+
+/// Compiles a user-supplied pattern, naming the setting in the error.
+fn compile_setting(name: &str, pattern: &str) -> Result<Regex, Box<dyn std::error::Error + Send + Sync>> {
+    Regex::new(pattern).map_err(|e| format!("INVALID_CONFIG: {} '{}' is not a valid regex: {}", name, pattern, e).into())
+}
+
+impl Session {
+    /// Fails with INVALID_CONFIG if a configured pattern doesn't compile, so a bad
+    /// pattern stops the run up front instead of misbehaving mid-session.
+    pub fn new(account: &Account, code: &str, mode: RunMode, settings: &SessionSettings) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let confirm_re = match settings.confirm_pattern.as_str() {
+            "" => None,
+            pattern => Some(compile_setting("confirmPattern", pattern)?),
+        };
+        let potion_count_re = compile_setting("potionCountPattern", &settings.potion_count_pattern)?;
+        let balance_re = match settings.balance_pattern.as_str() {
+            "" => None,
+            pattern => Some(compile_setting("balancePattern", pattern)?),
+        };
+        let mut reward_res = Vec::new();
+        for (item, pattern) in &settings.reward_patterns {
+            reward_res.push((item.clone(), compile_setting(&format!("rewardPatterns.{}", item), pattern)?));
+        }
+        reward_res.sort_by(|a, b| a.0.cmp(&b.0));
+        let steps = mode.steps();
+        let mut pending_modes: VecDeque<RunMode> = steps.clone().into();
+        let mode = pending_modes.pop_front().ok_or("INVALID_CONFIG: empty run mode sequence")?;
+        Ok(Self {
+            account: account.clone(),
+            code: code.to_string(),
+            completion: settings.completion_rule(mode.clone()),
+            mode,
+            pending_modes,
+            steps,
+            settings: settings.clone(),
+            state: GameState::Connected,
+            history: String::new(),
+            processed: HashMap::new(),
+            auto_sent: false,
+            mana_hits: 0,
+            last_command: None,
+            commands_sent: 0,
+            outbox: Vec::new(),
+            trigger: String::new(),
+            trace: VecDeque::new(),
+            continue_policy: None,
+            prompt_matcher: None,
+            unhandled_events: BTreeSet::new(),
+            confirm_re,
+            mask: CodeMask {
+                codes: std::iter::once(code.to_string()).chain(account.decrypt_spare_codes().iter().map(|c| c.trim().to_string())).collect(),
+                chunk_size: account.code_chunk_size,
+            },
+            potion_count_re,
+            balance_re,
+            balance: None,
+            work_done: false,
+            reward_res,
+            rewards: InventoryDelta::default(),
+            screen: VecDeque::new(),
+            menu_fallback: None,
+            confirm_steps: vec![0; settings.confirm_sequences.len()],
+            code_chunked: false,
+            spare_codes: account.decrypt_spare_codes().iter().map(|code| code.trim().to_string()).collect(),
+            pace: None,
+            selected_server: None,
+            server_attempt: 0,
+            output_tx: None,
+        })
+    }
+
+    /// Publish every non-empty output line on `tx` as it arrives.
+    pub fn set_output_sender(&mut self, tx: broadcast::Sender<String>) {
+        self.output_tx = Some(tx);
+    }
+
+    pub fn set_continue_policy(&mut self, policy: Option<ContinuePolicy>) {
+        self.continue_policy = policy;
+    }
+
+    pub fn set_prompt_matcher(&mut self, matcher: Option<Box<dyn PromptMatcher + Send + Sync>>) {
+        self.prompt_matcher = matcher;
+    }
+
+    /// One-line summary of where the session is, for periodic progress logs.
+    pub fn progress(&self) -> String {
+        let last_trigger = self.trace.iter().rev()
+            .find_map(|(_, entry)| entry.strip_prefix("trigger: "))
+            .unwrap_or("none");
+        format!("{}: {:?}, {} command(s) sent, last trigger: {}", self.account.name, self.state, self.commands_sent, last_trigger)
+    }
+
+    /// Ordered record of the triggers that fired and the commands sent.
+    pub fn trace(&self) -> Vec<(Instant, String)> {
+        self.trace.iter().cloned().collect()
+    }
+
+    /// The last non-empty output lines, oldest first: what the game showed when the session ended.
+    pub fn final_screen(&self) -> Vec<String> {
+        self.screen.iter().cloned().collect()
+    }
+
+    /// Items gained so far this session.
+    pub fn rewards(&self) -> InventoryDelta {
+        self.rewards.clone()
+    }
+
+    /// Commands sent this session, one per line in order, followed by the final
+    /// screen as comments, for pasting into a bug report. The restore code stays
+    /// masked, and only commands still in the trace are listed.
+    pub fn command_script(&self) -> String {
+        let mut script = format!("# {} ({:?})\n", self.account.name, self.mode);
+        for (_, entry) in &self.trace {
+            let Some(cmd) = entry.strip_prefix("sent: ") else { continue };
+            match cmd.strip_suffix(" (orient)") {
+                Some(cmd) => script.push_str(&format!("{}  # orient\n", cmd)),
+                None => script.push_str(&format!("{}\n", cmd)),
+            }
+        }
+        script.push_str("# final screen:\n");
+        for line in &self.screen {
+            script.push_str(&format!("#   {}\n", line));
+        }
+        script
+    }
+
+    /// Start of the first occurrence of `text` in the history that hasn't been answered.
+    fn find_new(&self, text: &str) -> Option<usize> {
+        let from = self.processed.get(text).copied().unwrap_or(0);
+        self.history[from..].find(text).map(|at| from + at)
+    }
+
+    /// `find_new` ignoring ASCII case; `text` must be lowercase.
+    fn find_new_ci(&self, text: &str) -> Option<usize> {
+        let from = self.processed.get(text).copied().unwrap_or(0);
+        self.history[from..].to_ascii_lowercase().find(text).map(|at| from + at)
+    }
+
+    /// The history searched for completion keywords: up to `completionKeywordWindow`
+    /// characters before the completion prompt at `prompt_at`, or everything when unset.
+    fn keyword_window(&self, prompt_at: usize) -> &str {
+        let window = self.settings.completion_keyword_window;
+        if window == 0 {
+            return &self.history;
+        }
+        let mut start = prompt_at.saturating_sub(window);
+        while !self.history.is_char_boundary(start) {
+            start -= 1;
+        }
+        &self.history[start..prompt_at]
+    }
+
+    /// Marks every occurrence of `text` currently in the history as answered.
+    fn mark_all(&mut self, text: &str) {
+        if let Some(at) = self.history.rfind(text) {
+            self.processed.insert(text.to_string(), at + text.len());
+        }
+    }
+
+    /// Every prompt text a rule answers: the fixed ones plus the configured ones.
+    fn known_prompts(&self) -> Vec<String> {
+        let mut prompts: Vec<String> = PROMPT_RULES.iter().map(|rule| rule.prompt.to_string()).collect();
+        prompts.extend(self.settings.menu_choices.iter().map(|choice| choice.prompt.clone()));
+        prompts.extend(self.settings.confirm_sequences.iter().flat_map(|seq| seq.steps.iter().map(|step| step.prompt.clone())));
+        prompts.extend(self.settings.potion_prompt.clone());
+        prompts.extend(self.completion.prompts.iter().cloned());
+        prompts.retain(|p| !p.is_empty());
+        prompts.sort();
+        prompts.dedup();
+        prompts
+    }
+
+    /// When one new prompt match lies inside a longer one (a configured prompt that
+    /// contains "Enter Command to use", say), only the longer, more specific prompt
+    /// is answered: the shorter occurrence is marked as handled for this pass.
+    fn suppress_overlaps(&mut self) {
+        let found: Vec<(String, usize)> = self.known_prompts().into_iter()
+            .filter_map(|p| self.find_new(&p).map(|at| (p, at)))
+            .collect();
+        for (inner, at) in &found {
+            let outer = found.iter().find(|(other, other_at)| {
+                other.len() > inner.len() && *other_at <= *at && at + inner.len() <= other_at + other.len()
+            });
+            if let Some((outer, _)) = outer {
+                self.note(format!("suppressed: '{}' inside '{}'", inner, outer));
+                self.mark_at(inner, *at);
+            }
+        }
+    }
+
+    /// Marks occurrences of `text` up to the one starting at `at` as answered.
+    fn mark_at(&mut self, text: &str, at: usize) {
+        self.processed.insert(text.to_string(), at + text.len());
+    }
+
+    /// Whether a completion prompt saw evidence of work (keywords, 'auto', or the continue policy).
+    pub fn did_work(&self) -> bool {
+        self.work_done
+    }
+
+    /// Last balance read from the output, if `balancePattern` is set and matched.
+    pub fn balance(&self) -> Option<u64> {
+        self.balance
+    }
+
+    fn low_balance(&self) -> bool {
+        matches!((self.balance, self.settings.min_balance), (Some(balance), Some(min)) if balance < min)
+    }
+
+    /// Server name the next server list is searched for.
+    fn target_server(&self) -> String {
+        match self.server_attempt {
+            0 => self.account.target_server.clone().unwrap_or_else(|| "Default".to_string()),
+            n => self.account.fallback_servers[n - 1].clone(),
+        }
+    }
+
+    /// Name of the server picked from the login list, if one was.
+    pub fn selected_server(&self) -> Option<ServerEntry> {
+        self.selected_server.clone()
+    }
+
+    /// Event names the server sent that this handler does not act on.
+    pub fn unhandled_events(&self) -> Vec<String> {
+        self.unhandled_events.iter().cloned().collect()
+    }
+
+    /// Every restore code this session may send, for masking outside the session.
+    pub fn code_mask(&self) -> CodeMask {
+        self.mask.clone()
+    }
+
+    fn note(&mut self, entry: String) {
+        if let Some(trigger) = entry.strip_prefix("trigger: ") {
+            self.trigger = trigger.to_string();
+        }
+        if self.trace.len() == TRACE_LIMIT {
+            self.trace.pop_front();
+        }
+        self.trace.push_back((Instant::now(), entry));
+    }
+
+    pub fn resume_point(&self) -> Option<ResumePoint> {
+        if self.state == GameState::Finished {
+            return None;
+        }
+        self.last_command.as_ref().map(|cmd| ResumePoint {
+            state: self.state,
+            last_command: cmd.clone(),
+            auto_sent: self.auto_sent,
+            mana_hits: self.mana_hits,
+            mode: self.mode.clone(),
+            pending_modes: self.pending_modes.iter().cloned().collect(),
+        })
+    }
+
+    /// Restores a previous session's progress and, per `resumeReprint`, sends a
+    /// command so the game prints the current prompt again.
+    pub fn resume(&mut self, point: &ResumePoint) {
+        self.state = point.state;
+        self.auto_sent = point.auto_sent;
+        self.mana_hits = point.mana_hits;
+        self.completion = self.settings.completion_rule(point.mode.clone());
+        self.mode = point.mode.clone();
+        self.pending_modes = point.pending_modes.iter().cloned().collect();
+        match self.settings.resume_reprint {
+            ResumeReprint::Last => {
+                println!("[ACTION] Resuming at {:?}, re-sending last command...", point.state);
+                self.send_command(&point.last_command);
+            },
+            ResumeReprint::Orient => {
+                println!("[ACTION] Resuming at {:?}, asking the game to show its screen...", point.state);
+                self.orient();
+            },
+            ResumeReprint::Wait => {
+                println!("[INFO] Resuming at {:?}, waiting for the game to print...", point.state);
+            },
+        }
+        // A second drop before anything new is sent resumes from the same point.
+        if self.last_command.is_none() {
+            self.last_command = Some(point.last_command.clone());
+        }
+    }
+
+    /// Switches to the next mode of a sequence, forgetting the previous mode's
+    /// progress and output so its completion words don't count for the new one.
+    fn start_mode(&mut self, mode: RunMode) {
+        self.completion = self.settings.completion_rule(mode.clone());
+        self.mode = mode;
+        self.auto_sent = false;
+        self.mana_hits = 0;
+        self.menu_fallback = None;
+        self.history.clear();
+        self.processed.clear();
+    }
+
+    /// Queues `orientCommand`, if set, to make the game print its current screen.
+    /// It isn't kept as the last command, so a resume never re-sends it.
+    pub fn orient(&mut self) {
+        if let Some(cmd) = self.settings.orient_command.clone().filter(|c| !c.is_empty()) {
+            self.note(format!("sent: {} (orient)", cmd));
+            self.commands_sent += 1;
+            self.outbox.push((cmd, String::new()));
+        }
+    }
+
+    /// Plays the whole run again after SESSION_COMPLETE: answers 'y' at the exit
+    /// prompt and starts over from the first mode. The command count carries over.
+    pub fn repeat(&mut self) {
+        self.pending_modes = self.steps.clone().into();
+        let first = self.pending_modes.pop_front().unwrap_or(RunMode::Daily);
+        self.note(format!("repeat: starting {:?} again", first));
+        self.start_mode(first);
+        self.send_command("y");
+        self.state = GameState::WaitingForCommandPrompt;
+    }
+
+    pub fn take_commands(&mut self) -> Vec<String> {
+        self.take_triggered_commands().into_iter().map(|(cmd, _)| cmd).collect()
+    }
+
+    /// Like `take_commands`, paired with the trigger that produced each command.
+    pub fn take_triggered_commands(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Delay to leave between the commands just taken, if they must be paced.
+    pub fn take_pace(&mut self) -> Option<Duration> {
+        self.pace.take()
+    }
+
+    fn send_command(&mut self, cmd: &str) {
+        let shown = if self.mask.hides(cmd) { "<restore code>" } else { cmd };
+        self.note(format!("sent: {}", shown));
+        self.last_command = Some(cmd.to_string());
+        self.commands_sent += 1;
+        self.outbox.push((cmd.to_string(), self.trigger.clone()));
+    }
+
+    /// Handles one `42[...]` socket.io event frame.
+    pub fn handle_event(&mut self, text: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.trigger.clear();
+        let json_part = &text[2..];
+        let event: serde_json::Value = match serde_json::from_str(json_part) {
+            Ok(v) => v,
+            Err(_) => return Ok(()),
+        };
+
+        if let Some((event_name, event_data)) = unwrap_event(&event, self.settings.event_envelope) {
+            if event_name == "output" {
+                 if let Some(data) = event_data {
+                     let texts: Vec<String> = self.settings.output_fields.iter()
+                         .filter_map(|field| output_text(&data[field.as_str()]))
+                         .collect();
+                     for output_text in texts {
+                         self.handle_output(&output_text)?;
+                         if self.commands_sent > self.settings.max_commands {
+                             println!("[ERROR] Sent {} commands this session (limit {}). Aborting.", self.commands_sent, self.settings.max_commands);
+                             self.note("limit: command cap exceeded".to_string());
+                             return Err("COMMAND_LIMIT_EXCEEDED".into());
+                         }
+                     }
+                 }
+            } else if event_name == "idle_timeout" || event_name == "disconnect" {
+                self.note(format!("event: {}", event_name));
+                return Err(format!("SERVER_{}", event_name.to_uppercase()).into());
+            } else if event_name == "activity_ping" || event_name == "user_count_update" {
+                return Ok(());
+            } else if self.unhandled_events.insert(event_name.to_string()) {
+                println!("[DEBUG] Unhandled event '{}': {}", event_name, json_part.chars().take(200).collect::<String>());
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_output(&mut self, output_text: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let clean_log = output_text.replace("\n", " ");
+        if !clean_log.trim().is_empty() {
+            match self.settings.terminal_log_chars {
+                0 => println!("[TERMINAL] {}", clean_log),
+                limit => println!("[TERMINAL] {}", clean_log.chars().take(limit).collect::<String>()),
+            }
+        }
+
+        for line in output_text.lines().map(str::trim_end).filter(|l| !l.is_empty()) {
+            if let Some(tx) = &self.output_tx {
+                // Fails only when nobody is subscribed.
+                let _ = tx.send(line.to_string());
+            }
+            self.screen.push_back(line.to_string());
+            if self.screen.len() > self.settings.final_screen_lines {
+                self.screen.pop_front();
+            }
+        }
+
+        let balance = self.balance_re.as_ref().and_then(|re| {
+            re.captures_iter(output_text).filter_map(|cap| cap.get(1)?.as_str().replace(',', "").parse::<u64>().ok()).last()
+        });
+        if let Some(balance) = balance.filter(|b| self.balance != Some(*b)) {
+            println!("[DEBUG] Balance: {}", balance);
+            self.balance = Some(balance);
+        }
+
+        for (item, re) in &self.reward_res {
+            let gained: i64 = re.captures_iter(output_text).filter_map(|cap| cap.get(1)?.as_str().replace(',', "").parse::<i64>().ok()).sum();
+            if gained != 0 {
+                println!("[DEBUG] Reward: {}x {}", gained, item);
+                self.rewards.add(item, gained);
+            }
+        }
+
+        let frame_start = self.history.len();
+        self.history.push_str(output_text);
+        // Never cut into the new frame or the tail it may complete a prompt with;
+        // an oversized frame is trimmed on the next one instead.
+        let mut drain_len = self.history.len().saturating_sub(HISTORY_LIMIT).min(frame_start.saturating_sub(TRIM_OVERLAP));
+        if drain_len > 0 {
+            while !self.history.is_char_boundary(drain_len) && drain_len > 0 { drain_len -= 1; }
+            self.history.replace_range(..drain_len, "");
+            for offset in self.processed.values_mut() {
+                *offset = offset.saturating_sub(drain_len);
+            }
+        }
+
+        let sent_before = self.commands_sent;
+        self.suppress_overlaps();
+
+        if !self.account.allow_gem_spend {
+            let spend = self.settings.premium_spend_patterns.iter()
+                .map(|p| p.to_lowercase())
+                .find_map(|p| self.find_new_ci(&p).map(|at| (p, at)));
+            if let Some((pattern, at)) = spend {
+                self.mark_at(&pattern, at);
+                // Whatever else this screen asks, it must not be answered with a 'y'.
+                for prompt in self.known_prompts() {
+                    self.mark_all(&prompt);
+                }
+                let decline = self.settings.premium_spend_decline.clone();
+                self.note(format!("trigger: premium spend '{}', declined with '{}'", pattern, decline));
+                println!("[ACTION] Prompt wants to spend gems ('{}'). Sending '{}'...", pattern, decline);
+                self.send_command(&decline);
+            }
+        }
+
+        if let Some(at) = self.find_new_ci(INVALID_COMMAND) {
+            self.mark_at(INVALID_COMMAND, at);
+            let fallback = self.settings.fallback_commands.get(self.mode.key()).cloned();
+            let menu_command = self.menu_fallback.clone().unwrap_or_else(|| self.mode.menu_command().to_string());
+            let menu_rejected = self.last_command.as_deref() == Some(menu_command.as_str());
+            match fallback {
+                // Only the menu shortcut matters; other rejected answers are left for the prompt to repeat.
+                _ if !menu_rejected => {
+                    self.note("ignored: invalid command, not for the menu shortcut".to_string());
+                },
+                Some(command) if self.menu_fallback.is_none() => {
+                    self.note(format!("trigger: invalid command, falling back to '{}'", command));
+                    println!("[WARN] '{}' was rejected. Trying fallback command '{}'...", self.mode.menu_command(), command);
+                    // The menu usually reprints with the error; the fallback answers it.
+                    self.mark_all(COMMAND_PROMPT);
+                    self.send_command(&command);
+                    self.menu_fallback = Some(command);
+                    self.state = GameState::SentD;
+                },
+                _ => {
+                    println!("[ERROR] Invalid Command. Restarting session...");
+                    self.note("trigger: invalid command".to_string());
+                    return Err("INVALID_COMMAND_RESTART".into());
+                }
+            }
+        }
+
+        if self.find_new(COMMAND_PROMPT).is_some() {
+            self.mark_all(COMMAND_PROMPT);
+            self.note("trigger: command prompt".to_string());
+            let command = self.menu_fallback.clone().unwrap_or_else(|| self.mode.menu_command().to_string());
+            println!("[ACTION] Sending '{}'...", command);
+            self.send_command(&command);
+            self.state = GameState::SentD;
+        }
+
+        let code_rejected = self.find_new_ci(INCORRECT_CODE);
+        let chunk_size = self.account.code_chunk_size.filter(|size| *size > 0 && self.code.chars().count() > *size);
+        if let (Some(at), Some(size), false) = (code_rejected, chunk_size, self.code_chunked) {
+            if self.last_command.as_deref() == Some(self.code.as_str()) {
+                self.mark_at(INCORRECT_CODE, at);
+                self.mark_all(RESTORE_PROMPT);
+                let chars: Vec<char> = self.code.chars().collect();
+                let chunks: Vec<String> = chars.chunks(size).map(|c| c.iter().collect()).collect();
+                self.note(format!("trigger: restore code rejected, resending in {} chunks", chunks.len()));
+                println!("[ACTION] Restore code rejected. Resending it in {} chunks of {}...", chunks.len(), size);
+                self.code_chunked = true;
+                for chunk in chunks {
+                    self.send_command(&chunk);
+                }
+                self.pace = Some(Duration::from_millis(self.account.code_chunk_delay_ms.unwrap_or(300)));
+                self.state = GameState::SentCode;
+            }
+        }
+
+        if let Some(at) = self.find_new_ci(INCORRECT_CODE) {
+            if let Some(next) = self.spare_codes.pop_front() {
+                self.mark_at(INCORRECT_CODE, at);
+                self.mark_all(RESTORE_PROMPT);
+                let left = self.spare_codes.len();
+                self.note(format!("trigger: restore code rejected, trying the next one ({} left)", left));
+                println!("[ACTION] Restore code rejected. Trying the next code ({} left after it)...", left);
+                self.code = next;
+                self.code_chunked = false;
+                let code = self.code.clone();
+                self.send_command(&code);
+                self.state = GameState::SentCode;
+            }
+        }
+
+        if self.find_new(RESTORE_PROMPT).is_some() {
+            self.mark_all(RESTORE_PROMPT);
+            self.note("trigger: restore code prompt".to_string());
+            println!("[ACTION] Sending Restore Code...");
+            let code = self.code.clone();
+            self.send_command(&code);
+            self.state = GameState::SentCode;
+        }
+
+        // A full server: the game goes back to login, and the next fallback gets picked from the list,
+        // which may have arrived in this same frame.
+        let full_at = self.find_new_ci(SERVER_FULL);
+        if let (Some(at), true) = (full_at, self.server_attempt < self.account.fallback_servers.len()) {
+            self.mark_at(SERVER_FULL, at);
+            let full = self.target_server();
+            self.server_attempt += 1;
+            self.note(format!("trigger: server {} full, trying {}", full, self.target_server()));
+            println!("[WARN] Server {} is full. Trying fallback server {}...", full, self.target_server());
+            self.state = GameState::SentCode;
+        }
+
+        if self.find_new(SERVER_PROMPT).is_some() {
+            let target = self.target_server();
+            if target != "Default" {
+                // Wait for more output when the target isn't listed yet rather than guessing an index.
+                // Only the latest list; an earlier one stays in history after a full server.
+                let list_at = self.history.rfind(SERVER_PROMPT).unwrap_or(0);
+                let candidates: Vec<ServerEntry> = parse_server_list(&self.history[list_at..]).into_iter().filter(|entry| {
+                    entry.name.contains(target.as_str()) || (target.to_lowercase() == "all" && entry.name.contains("All of them"))
+                }).collect();
+                let matches = candidates.len();
+                if let Some(entry) = pick_server(self.settings.server_strategy, candidates, &self.account.server_last_used) {
+                    self.note(format!("trigger: server list, picked {} ({}) of {} match(es)", entry.index, entry.name, matches));
+                    println!("[ACTION] Selecting server index: {} ({})", entry.index, entry.name);
+                    self.send_command(&entry.index.to_string());
+                    self.selected_server = Some(entry);
+                    self.mark_all(SERVER_PROMPT);
+                    self.state = GameState::ServerSelected;
+                }
+            }
+        }
+
+        for choice in self.settings.menu_choices.clone() {
+            // Wait for more output when the label isn't listed yet.
+            let Some(at) = self.find_new(&choice.prompt) else { continue };
+            if let Some(index) = menu_index(&self.history[at..], &choice.label) {
+                self.mark_all(&choice.prompt);
+                self.note(format!("trigger: menu '{}', picked {} ({})", choice.prompt, index, choice.label));
+                println!("[ACTION] Selecting '{}' (index {}) at '{}'...", choice.label, index, choice.prompt);
+                self.send_command(&index.to_string());
+            }
+        }
+
+        for (i, sequence) in self.settings.confirm_sequences.clone().iter().enumerate().filter(|(_, seq)| !seq.steps.is_empty()) {
+            loop {
+                let step = self.confirm_steps[i];
+                let current = &sequence.steps[step];
+                let Some(at) = self.find_new(&current.prompt) else {
+                    // A fresh first prompt starts the chain over.
+                    if step > 0 && self.find_new(&sequence.steps[0].prompt).is_some() {
+                        self.confirm_steps[i] = 0;
+                        continue;
+                    }
+                    break;
+                };
+                self.mark_at(&current.prompt, at);
+                self.note(format!("trigger: confirm step {}/{} '{}'", step + 1, sequence.steps.len(), current.prompt));
+                println!("[ACTION] Confirm step {}/{}: sending '{}'...", step + 1, sequence.steps.len(), current.reply);
+                self.send_command(&current.reply);
+                let next = (step + 1) % sequence.steps.len();
+                self.confirm_steps[i] = next;
+                if next == 0 {
+                    break;
+                }
+                // The next step only counts when it comes after this one.
+                let next_prompt = &sequence.steps[next].prompt;
+                let from = self.processed.get(next_prompt).copied().unwrap_or(0).max(at + current.prompt.len());
+                self.processed.insert(next_prompt.clone(), from);
+            }
+        }
+
+        if self.find_new(MANA_PROMPT).is_some() {
+            self.mark_all(MANA_PROMPT);
+            self.note("trigger: mana prompt".to_string());
+            self.mana_hits += 1;
+            if self.low_balance() {
+                println!("[ACTION] Balance {} is below the minimum, sending 'n' for mana...", self.balance.unwrap_or(0));
+                self.note("limit: balance below minimum, not spending".to_string());
+                self.send_command("n");
+            } else {
+                let response = self.account.mana_response(self.mode.clone(), self.mana_hits - 1);
+                println!("[ACTION] Sending '{}' for mana (prompt #{})...", response, self.mana_hits);
+                self.send_command(&response);
+            }
+            self.state = GameState::WaitingProcedure;
+        }
+
+        if self.find_new(REFILL_PROMPT).is_some() {
+            self.mark_all(REFILL_PROMPT);
+            self.note("trigger: refill mana prompt".to_string());
+            if self.low_balance() {
+                println!("[ACTION] Balance {} is below the minimum, sending 'n' to refill...", self.balance.unwrap_or(0));
+                self.note("limit: balance below minimum, not refilling".to_string());
+                self.send_command("n");
+            } else if self.account.refill_mana.unwrap_or(true) {
+                println!("[ACTION] Sending 'y' to refill mana...");
+                self.send_command("y");
+            } else {
+                println!("[ACTION] Refill disabled for this account, sending 'n'...");
+                self.send_command("n");
+            }
+        }
+
+        if let Some(prompt) = self.settings.potion_prompt.clone().filter(|p| !p.is_empty()) {
+            if let Some(at) = self.find_new(&prompt) {
+                // Only the screen since the previous potion prompt: older counts are stale.
+                let from = self.processed.get(&prompt).copied().unwrap_or(0);
+                let quantity = potion_quantity(&self.history[from..at], &self.potion_count_re, self.settings.potion_quantity);
+                self.mark_all(&prompt);
+                self.note(format!("trigger: potion prompt (quantity {})", quantity));
+                println!("[ACTION] Sending '{}' for potion quantity...", quantity);
+                self.send_command(&quantity.to_string());
+            }
+        }
+
+        if self.find_new(NEXT_EVENT_PROMPT).is_some() {
+            self.mark_all(NEXT_EVENT_PROMPT);
+            self.note("trigger: next event prompt".to_string());
+            if !self.auto_sent {
+                println!("[ACTION] Sending 'auto'...");
+                self.send_command("auto");
+                self.auto_sent = true;
+                self.state = GameState::RapidFire;
+            } else {
+                println!("[ACTION] Sending 'exit'...");
+                self.send_command("exit");
+            }
+        }
+
+        let completion_prompt = self.completion.prompts.iter().find_map(|p| self.find_new(p).map(|at| (p.clone(), at)));
+        if let Some((prompt, prompt_at)) = completion_prompt {
+            let looks_done = match &self.continue_policy {
+                Some(should_continue) => !should_continue(),
+                None => {
+                    let h_low = self.keyword_window(prompt_at).to_lowercase();
+                    self.completion.keywords.iter().any(|k| contains_word(&h_low, &k.to_lowercase())) ||
+                    self.auto_sent || (self.mode == RunMode::Handout && self.mana_hits > 0)
+                }
+            };
+
+            self.note(format!("trigger: completion prompt '{}' (work done: {})", prompt, looks_done));
+            self.work_done |= looks_done;
+            if looks_done && !self.pending_modes.is_empty() {
+                let next = self.pending_modes.pop_front().unwrap_or(RunMode::Daily);
+                println!("[INFO] {:?} finished. Returning to menu for {:?}...", self.mode, next);
+                self.note(format!("mode {:?} done, next {:?}", self.mode, next));
+                self.start_mode(next);
+                self.send_command("y");
+                self.state = GameState::WaitingForCommandPrompt;
+            } else if looks_done {
+                println!("[INFO] Work confirmed in history. Ending session.");
+                self.state = GameState::Finished;
+                return Err("SESSION_COMPLETE".into());
+            } else {
+                if self.continue_policy.is_some() {
+                    println!("[INFO] Continue policy says keep playing. Returning to menu...");
+                } else {
+                    println!("[WARN] Exit prompt seen but no work indicators found. Returning to menu...");
+                }
+                self.mark_all(&prompt);
+                self.send_command("y");
+            }
+        }
+
+        if self.commands_sent == sent_before {
+            if let Some(matcher) = self.prompt_matcher.as_mut() {
+                let commands = matcher.on_output(&self.history, output_text, &self.state);
+                if !commands.is_empty() {
+                    self.note(format!("trigger: prompt matcher ({} command(s))", commands.len()));
+                    println!("[ACTION] Prompt matcher answered with {} command(s)...", commands.len());
+                }
+                for cmd in commands {
+                    self.send_command(&cmd);
+                }
+            }
+        }
+
+        // Generic (y/n) prompts only get the default answer when no rule above answered.
+        if self.commands_sent == sent_before && self.confirm_re.as_ref().is_some_and(|re| re.is_match(output_text)) {
+            let answer = self.settings.confirm_answer.clone();
+            self.note(format!("trigger: generic confirm prompt ({})", answer));
+            println!("[ACTION] Unrecognised (y/n) prompt, sending default '{}'...", answer);
+            self.send_command(&answer);
+        }
+
+        let h_low = self.history.to_lowercase();
+        if let Some(pattern) = self.settings.verification_patterns.iter().find(|p| h_low.contains(&p.to_lowercase())) {
+            println!("[ERROR] Human verification prompt detected ('{}'). Aborting session.", pattern);
+            self.note(format!("trigger: verification prompt '{}'", pattern));
+            return Err("HUMAN_VERIFICATION_REQUIRED".into());
+        }
+        if let Some(pattern) = self.settings.banned_patterns.iter().find(|p| h_low.contains(&p.to_lowercase())) {
+            println!("[ERROR] Account is banned or suspended ('{}'). Aborting session.", pattern);
+            self.note(format!("trigger: banned '{}'", pattern));
+            return Err("ACCOUNT_BANNED".into());
+        }
+        if let Some(pattern) = self.settings.maintenance_patterns.iter().find(|p| self.find_new_ci(&p.to_lowercase()).is_some()) {
+            println!("[ERROR] Server is under maintenance ('{}').", pattern);
+            self.note(format!("trigger: maintenance '{}'", pattern));
+            return Err("SERVER_MAINTENANCE".into());
+        }
+        if let Some(pattern) = self.settings.client_outdated_patterns.iter().find(|p| self.find_new_ci(&p.to_lowercase()).is_some()) {
+            println!("[ERROR] Server asks for a client update ('{}'). The protocol may have changed.", pattern);
+            self.note(format!("trigger: client outdated '{}'", pattern));
+            return Err("CLIENT_OUTDATED".into());
+        }
+        if let Some(pattern) = self.settings.event_unavailable_patterns.iter().find(|p| h_low.contains(&p.to_lowercase())) {
+            println!("[INFO] Event not available right now ('{}'). Ending session.", pattern);
+            self.note(format!("trigger: event unavailable '{}'", pattern));
+            return Err("EVENT_UNAVAILABLE".into());
+        }
+        if let Some(pattern) = self.settings.code_used_patterns.iter().find(|p| h_low.contains(&p.to_lowercase())) {
+            println!("[INFO] Restore code was already used today ('{}'). Ending session.", pattern);
+            self.note(format!("trigger: code already used '{}'", pattern));
+            return Err("CODE_ALREADY_USED".into());
+        }
+        if let Some(pattern) = self.settings.rate_limit_patterns.iter().find(|p| h_low.contains(&p.to_lowercase())) {
+            println!("[ERROR] Rate limit message detected ('{}').", pattern);
+            self.note(format!("trigger: rate limit '{}'", pattern));
+            return Err("RATE_LIMITED".into());
+        }
+        if !self.account.codes.is_empty() && self.find_new_ci(INCORRECT_CODE).is_some() {
+            println!("[ERROR] Every restore code of this account was rejected.");
+            self.note("trigger: all restore codes rejected".to_string());
+            return Err("ALL_CODES_INVALID".into());
+        }
+        if h_low.contains("zigza error") || self.find_new_ci(INCORRECT_CODE).is_some() {
+            println!("[ERROR] Account Error Detected (Zigza/Code)!");
+            self.note("trigger: zigza / incorrect code".to_string());
+            return Err("ZIGZA_DETECTED".into());
+        }
+        if self.find_new_ci(SERVER_FULL).is_some() {
+            println!("[ERROR] Server Capacity Error!");
+            self.note("trigger: server full".to_string());
+            return Err("SERVER_FULL".into());
+        }
+        if h_low.contains("logged in users") {
+            println!("[ERROR] Session Expired / Login Required!");
+            self.note("trigger: login required".to_string());
+            return Err("LOGIN_REQUIRED".into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(mode: RunMode, settings: &SessionSettings) -> Session {
+        let account = Account { name: "test".to_string(), ..Default::default() };
+        Session::new(&account, "123456", mode, settings).expect("valid settings")
+    }
+
+    /// Feeds `text` as one `output` event and returns the commands it produced.
+    fn feed(session: &mut Session, text: &str) -> Result<Vec<String>, String> {
+        feed_event(session, serde_json::json!(["output", {"data": text}]))
+    }
+
+    fn feed_event(session: &mut Session, event: serde_json::Value) -> Result<Vec<String>, String> {
+        session.handle_event(&format!("42{}", event)).map_err(|e| e.to_string())?;
+        Ok(session.take_commands())
+    }
+
+    fn entries(list: &[ServerEntry]) -> Vec<(u32, &str)> {
+        list.iter().map(|entry| (entry.index, entry.name.as_str())).collect()
+    }
+
+    #[test]
+    fn server_list_keeps_nested_parentheses() {
+        let list = parse_server_list("Which acc u want to Login\n1--> Hero (E-1)\n2 --> Alt (E-15 (Asia))\n");
+        assert_eq!(entries(&list), [(1, "E-1"), (2, "E-15 (Asia)")]);
+    }
+
+    #[test]
+    fn server_list_tolerates_malformed_entries() {
+        let list = parse_server_list("1-->NoParens\n2--> Hero (E-3\nx--> junk\n3 -->   \n");
+        assert_eq!(entries(&list), [(1, "NoParens"), (2, "E-3"), (3, "")]);
+    }
+
+    #[test]
+    fn server_entries_sharing_a_line_are_split() {
+        let list = parse_server_list("1--> A (E-1) 2--> B (E-2)");
+        assert_eq!(entries(&list), [(1, "E-1"), (2, "E-2")]);
+    }
+
+    #[test]
+    fn menu_entries_sharing_a_line_are_split() {
+        let menu = parse_menu("1--> Easy 2--> Normal 3 --> Hard\n");
+        let labels: Vec<_> = menu.iter().map(|entry| (entry.index, entry.label.as_str())).collect();
+        assert_eq!(labels, [(1, "Easy"), (2, "Normal"), (3, "Hard")]);
+    }
+
+    #[test]
+    fn output_text_is_read_from_configured_fields() {
+        let settings = SessionSettings { output_fields: vec!["data".to_string(), "message".to_string()], ..Default::default() };
+        let mut s = session(RunMode::Daily, &settings);
+        let event = serde_json::json!(["output", {"message": "Enter Command to use"}]);
+        assert_eq!(feed_event(&mut s, event.clone()), Ok(vec!["d".to_string()]));
+
+        let mut s = session(RunMode::Daily, &SessionSettings::default());
+        assert_eq!(feed_event(&mut s, event), Ok(vec![]));
+    }
+
+    #[test]
+    fn potion_quantity_is_at_least_one() {
+        let re = Regex::new(r"(?i)(\d+)\s+potions?").unwrap();
+        assert_eq!(potion_quantity("You have 0 potions", &re, None), 1);
+        assert_eq!(potion_quantity("You have 0 potions", &re, Some(3)), 1);
+        assert_eq!(potion_quantity("You have 5 potions", &re, Some(3)), 3);
+        assert_eq!(potion_quantity("Nothing to count", &re, Some(3)), 1);
+    }
+
+    #[test]
+    fn potion_count_comes_from_the_current_screen() {
+        let settings = SessionSettings {
+            potion_prompt: Some("How many potions?".to_string()),
+            ..Default::default()
+        };
+        let mut s = session(RunMode::Daily, &settings);
+        assert_eq!(feed(&mut s, "You have 5 potions\nHow many potions?"), Ok(vec!["5".to_string()]));
+        assert_eq!(feed(&mut s, "Potions used.\nHow many potions?"), Ok(vec!["1".to_string()]));
+    }
+
+    #[test]
+    fn command_cap_ends_the_session() {
+        let settings = SessionSettings { max_commands: 2, ..Default::default() };
+        let mut s = session(RunMode::Daily, &settings);
+        assert_eq!(feed(&mut s, "Enter Command to use"), Ok(vec!["d".to_string()]));
+        assert_eq!(feed(&mut s, "Enter Restore code"), Ok(vec!["123456".to_string()]));
+        assert_eq!(feed(&mut s, "Press y to spend mana on event stages"), Err("COMMAND_LIMIT_EXCEEDED".to_string()));
+    }
+
+    #[test]
+    fn specific_rule_wins_over_generic_confirm() {
+        let settings = SessionSettings { confirm_pattern: r"(?im)\(y/n\)\s*:?\s*$".to_string(), ..Default::default() };
+        let mut s = session(RunMode::Daily, &settings);
+        for text in ["Enter Command to use", "Enter Restore code", "Which acc u want to Login\n1--> Hero (E-1)"] {
+            feed(&mut s, text).unwrap();
+        }
+        assert_eq!(feed(&mut s, "Press y to spend mana on event stages (y/n)"), Ok(vec!["y".to_string()]));
+        assert_eq!(feed(&mut s, "Skip the cutscene? (y/n)"), Ok(vec!["n".to_string()]));
+    }
+
+    #[test]
+    fn generic_confirm_is_off_by_default() {
+        let mut s = session(RunMode::Daily, &SessionSettings::default());
+        assert_eq!(feed(&mut s, "Skip the cutscene? (y/n)"), Ok(vec![]));
+    }
+
+    #[test]
+    fn placeholder_codes_are_recognised() {
+        let settings = SessionSettings { placeholder_codes: vec![" dummy ".to_string()], ..Default::default() };
+        for code in ["0000", "xxxx", "XxXx", "todo", "TODO", " changeme\n", "Dummy"] {
+            assert!(settings.is_placeholder_code(code), "{:?}", code);
+        }
+        for code in ["123456", "0", "", "todo1", "0001"] {
+            assert!(!settings.is_placeholder_code(code), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn full_server_and_new_list_in_one_frame() {
+        let account = Account {
+            name: "test".to_string(),
+            target_server: Some("E-1".to_string()),
+            fallback_servers: vec!["E-2".to_string()],
+            ..Default::default()
+        };
+        let mut s = Session::new(&account, "123456", RunMode::Daily, &SessionSettings::default()).expect("valid settings");
+        let list = "Which acc u want to Login\n1--> Hero (E-1)\n2--> Alt (E-2)";
+        feed(&mut s, "Enter Command to use").unwrap();
+        feed(&mut s, "Enter Restore code").unwrap();
+        assert_eq!(feed(&mut s, list), Ok(vec!["1".to_string()]));
+        let frame = format!("Server has reached the maximum limit of restore\n{}", list);
+        assert_eq!(feed(&mut s, &frame), Ok(vec!["2".to_string()]));
+    }
+
+    #[test]
+    fn answers_carry_their_trigger() {
+        let mut s = session(RunMode::Daily, &SessionSettings::default());
+        for text in ["Enter Command to use", "Enter Restore code", "Which acc u want to Login\n1--> Hero (E-1)"] {
+            feed(&mut s, text).unwrap();
+        }
+        s.handle_event(&format!("42{}", serde_json::json!(["output", {"data": "Press y to spend mana on event stages"}]))).unwrap();
+        let mana = s.take_triggered_commands();
+        s.handle_event(&format!("42{}", serde_json::json!(["output", {"data": "DO U WANT TO REFILL MANA"}]))).unwrap();
+        let refill = s.take_triggered_commands();
+        assert_eq!(mana, [("y".to_string(), "mana prompt".to_string())]);
+        assert_eq!(refill, [("y".to_string(), "refill mana prompt".to_string())]);
+    }
+
+    #[test]
+    fn completion_prompt_without_work_keeps_playing() {
+        let mut s = session(RunMode::Daily, &SessionSettings::default());
+        assert_eq!(feed(&mut s, "Press y to perform more commands"), Ok(vec!["y".to_string()]));
+    }
+
+    #[test]
+    fn answered_completion_prompt_does_not_fire_again() {
+        let mut s = session(RunMode::Daily, &SessionSettings::default());
+        assert_eq!(feed(&mut s, "Press y to perform more commands"), Ok(vec!["y".to_string()]));
+        assert_eq!(feed(&mut s, "Loading..."), Ok(vec![]));
+    }
+
+    #[test]
+    fn completion_prompt_after_work_ends_session() {
+        let mut s = session(RunMode::Daily, &SessionSettings::default());
+        assert_eq!(feed(&mut s, "Stage success\nPress y to perform more commands"), Err("SESSION_COMPLETE".to_string()));
+        assert!(s.did_work());
+    }
+
+    #[test]
+    fn output_field_as_string() {
+        let mut s = session(RunMode::Daily, &SessionSettings::default());
+        let event = serde_json::json!(["output", {"data": "Loading...\nEnter Command to use"}]);
+        assert_eq!(feed_event(&mut s, event), Ok(vec!["d".to_string()]));
+        assert_eq!(s.final_screen(), ["Loading...", "Enter Command to use"]);
+    }
+
+    #[test]
+    fn output_field_as_array_of_lines() {
+        let mut s = session(RunMode::Daily, &SessionSettings::default());
+        let event = serde_json::json!(["output", {"data": ["Loading...", 7, "Enter Command to use"]}]);
+        assert_eq!(feed_event(&mut s, event), Ok(vec!["d".to_string()]));
+        assert_eq!(s.final_screen(), ["Loading...", "Enter Command to use"]);
+    }
+
+    #[test]
+    fn object_event_envelope() {
+        let settings = SessionSettings { event_envelope: EventEnvelope::Object, ..Default::default() };
+        let mut s = session(RunMode::Daily, &settings);
+        let event = serde_json::json!({"event": "output", "data": {"data": "Enter Command to use"}});
+        assert_eq!(feed_event(&mut s, event), Ok(vec!["d".to_string()]));
+        // The array layout isn't read under the object envelope.
+        assert_eq!(feed(&mut s, "Enter Command to use"), Ok(vec![]));
+    }
+
+    #[test]
+    fn array_event_envelope_is_the_default() {
+        let mut s = session(RunMode::Daily, &SessionSettings::default());
+        let object = serde_json::json!({"event": "output", "data": {"data": "Enter Command to use"}});
+        assert_eq!(feed_event(&mut s, object), Ok(vec![]));
+        assert_eq!(feed(&mut s, "Enter Command to use"), Ok(vec!["d".to_string()]));
+    }
+
+    #[test]
+    fn daily_and_handout_flows() {
+        let cases = [
+            (RunMode::Daily, "d", "y"),
+            (RunMode::Handout, "ho", "ho"),
+        ];
+        for (mode, menu, mana) in cases {
+            let mut s = session(mode.clone(), &SessionSettings::default());
+            let steps = [
+                ("Enter Command to use", vec![menu]),
+                ("Enter Restore code", vec!["123456"]),
+                ("Which acc u want to Login\n1--> Hero (E-1)", vec![]),
+                ("Press y to spend mana on event stages", vec![mana]),
+                ("DO U WANT TO REFILL MANA", vec!["y"]),
+                ("next: Go to the next event", vec!["auto"]),
+            ];
+            for (text, expected) in steps {
+                assert_eq!(feed(&mut s, text), Ok(expected.iter().map(|c| c.to_string()).collect()), "{:?}: {}", mode, text);
+            }
+            assert_eq!(feed(&mut s, "Stage success\nPress y to perform more commands"), Err("SESSION_COMPLETE".to_string()), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn only_the_longer_of_overlapping_prompts_fires() {
+        let settings = SessionSettings {
+            potion_prompt: Some("Enter Command to use potions".to_string()),
+            potion_quantity: Some(2),
+            ..Default::default()
+        };
+        let mut s = session(RunMode::Daily, &settings);
+        assert_eq!(feed(&mut s, "You have 5 potions\nEnter Command to use potions"), Ok(vec!["2".to_string()]));
+        // A later plain command prompt is still answered.
+        assert_eq!(feed(&mut s, "Enter Command to use"), Ok(vec!["d".to_string()]));
+    }
+
+    #[test]
+    fn spare_codes_are_tried_until_all_are_rejected() {
+        let account = Account {
+            name: "test".to_string(),
+            codes: vec![Account::encrypt_code_str("654321"), Account::encrypt_code_str(" 111222 ")],
+            ..Default::default()
+        };
+        let mut s = Session::new(&account, "123456", RunMode::Daily, &SessionSettings::default()).expect("valid settings");
+        let rejected = "Incorrect restore code\nEnter Restore code";
+        assert_eq!(feed(&mut s, "Enter Command to use"), Ok(vec!["d".to_string()]));
+        assert_eq!(feed(&mut s, "Enter Restore code"), Ok(vec!["123456".to_string()]));
+        assert_eq!(feed(&mut s, rejected), Ok(vec!["654321".to_string()]));
+        assert_eq!(feed(&mut s, rejected), Ok(vec!["111222".to_string()]));
+        assert_eq!(feed(&mut s, rejected), Err("ALL_CODES_INVALID".to_string()));
+    }
+
+    #[test]
+    fn completion_keywords_are_whole_words_near_the_prompt() {
+        let prompt = "Press y to perform more commands";
+        let mut s = session(RunMode::Daily, &SessionSettings::default());
+        let old_news = format!("You have already met the elder.\n{}\n{}", "-".repeat(2000), prompt);
+        assert_eq!(feed(&mut s, &old_news), Ok(vec!["y".to_string()]));
+        assert_eq!(feed(&mut s, &format!("The hall is undone.\n{}", prompt)), Ok(vec!["y".to_string()]));
+        assert_eq!(feed(&mut s, &format!("Stage cleared successfully!\n{}", prompt)), Err("SESSION_COMPLETE".to_string()));
+    }
+
+    #[test]
+    fn prompt_at_the_start_of_an_oversized_frame_is_answered() {
+        let mut s = session(RunMode::Daily, &SessionSettings::default());
+        let frame = format!("Enter Command to use\n{}", "x".repeat(16000));
+        assert_eq!(feed(&mut s, &frame), Ok(vec!["d".to_string()]));
+    }
+
+    #[test]
+    fn prompt_split_across_the_trim_point_is_answered() {
+        let mut s = session(RunMode::Daily, &SessionSettings::default());
+        assert_eq!(feed(&mut s, &format!("{}\nEnter Command", "x".repeat(HISTORY_LIMIT - 10))), Ok(vec![]));
+        assert_eq!(feed(&mut s, &format!(" to use\n{}", "y".repeat(HISTORY_LIMIT))), Ok(vec!["d".to_string()]));
+    }
+
+    #[test]
+    fn restore_code_is_trimmed_and_checked() {
+        assert_eq!(clean_code(" 123456\n"), Ok("123456"));
+        assert_eq!(clean_code(" \r\n"), Err("MISSING_CODE"));
+        assert_eq!(clean_code("123\u{0}456"), Err("INVALID_CODE_FORMAT"));
+        assert_eq!(clean_code("123\u{1b}[A"), Err("INVALID_CODE_FORMAT"));
+    }
+}
@@ -1,21 +1,131 @@
 use futures_util::{SinkExt, StreamExt, stream::{SplitSink, SplitStream}};
 use serde_json::json;
+use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
-use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::{client_async, MaybeTlsStream, WebSocketStream};
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Request;
 use tokio_tungstenite::tungstenite::http::HeaderValue;
-use tokio_tungstenite::tungstenite::Message;
-use regex::Regex;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 
-use crate::db::Account; // Import Account struct
+use crate::db::{Account, CompletionRule, PausePolicy, SessionSettings};
+use super::session::{clean_code, CodeMask, ContinuePolicy, InventoryDelta, PromptMatcher, PromptReplies, ResumePoint, ServerEntry, Session, MORE_COMMANDS_PROMPT};
 
 const BASE_URL: &str = "wss://evertext.sytes.net/socket.io/?EIO=4&transport=websocket";
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum RunMode {
     Daily,
     Handout,
+    // Runs each mode to completion in turn on the same login, going back to
+    // the menu in between.
+    Sequence(Vec<RunMode>),
+}
+
+impl RunMode {
+    /// The plain modes this mode runs, in order. A plain mode is its only step.
+    pub fn steps(&self) -> Vec<RunMode> {
+        match self {
+            RunMode::Sequence(modes) => modes.iter().flat_map(|m| m.steps()).collect(),
+            plain => vec![plain.clone()],
+        }
+    }
+
+    /// Key used for per-mode settings stored on an account.
+    /// A sequence uses the settings of its first step.
+    pub fn key(&self) -> &'static str {
+        match self {
+            RunMode::Daily => "daily",
+            RunMode::Handout => "handout",
+            RunMode::Sequence(modes) => modes.first().map_or("daily", |m| m.key()),
+        }
+    }
+
+    /// Menu command sent at "Enter Command to use".
+    pub fn menu_command(&self) -> &'static str {
+        match self {
+            RunMode::Daily => "d",
+            RunMode::Handout => "ho",
+            RunMode::Sequence(modes) => modes.first().map_or("d", |m| m.menu_command()),
+        }
+    }
+
+    /// Mana prompt answers used when the account does not override them.
+    pub fn default_mana_responses(&self) -> Vec<String> {
+        match self {
+            RunMode::Daily => vec!["y".to_string()],
+            RunMode::Handout => vec!["ho".to_string(), "y".to_string()],
+            RunMode::Sequence(modes) => modes.first().map_or_else(|| vec!["y".to_string()], |m| m.default_mana_responses()),
+        }
+    }
+
+    /// Completion prompts and keywords used when the settings don't override them.
+    pub fn default_completion(&self) -> CompletionRule {
+        let words = |list: &[&str]| list.iter().map(|w| w.to_string()).collect();
+        CompletionRule {
+            prompts: vec![MORE_COMMANDS_PROMPT.to_string()],
+            keywords: words(&["success", "successful", "successfully", "finish", "finished", "done", "already"]),
+        }
+    }
+}
+
+impl std::str::FromStr for RunMode {
+    type Err = String;
+
+    /// Parses "daily", "handout", or a comma-separated sequence such as "handout,daily".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(',') {
+            let modes = s.split(',').map(|part| part.trim().parse()).collect::<Result<Vec<RunMode>, _>>()?;
+            return Ok(RunMode::Sequence(modes));
+        }
+        match s.trim().to_lowercase().as_str() {
+            "daily" => Ok(RunMode::Daily),
+            "handout" => Ok(RunMode::Handout),
+            other => Err(format!("unknown run mode '{}' (expected daily, handout, or a comma-separated list)", other)),
+        }
+    }
+}
+
+/// Counts heartbeat windows (pingInterval + pingTimeout) that pass without a server ping.
+struct Heartbeat {
+    window: Duration,
+    max_missed: u32,
+    last_ping: Instant,
+    missed: u32,
+}
+
+#[derive(Debug, PartialEq)]
+enum Beat {
+    Alive,
+    Missed(u32),
+    TimedOut(u32),
+}
+
+impl Heartbeat {
+    fn new(ping_interval_ms: u64, ping_timeout_ms: u64, max_missed: u32, now: Instant) -> Self {
+        Heartbeat { window: Duration::from_millis(ping_interval_ms + ping_timeout_ms), max_missed, last_ping: now, missed: 0 }
+    }
+
+    /// A `2` ping arrived: the connection is alive again.
+    fn ping(&mut self, now: Instant) {
+        self.last_ping = now;
+        self.missed = 0;
+    }
+
+    /// Counts a miss when a whole window passed since the last ping or miss.
+    fn check(&mut self, now: Instant) -> Beat {
+        if now.saturating_duration_since(self.last_ping) <= self.window {
+            return Beat::Alive;
+        }
+        self.missed += 1;
+        self.last_ping = now;
+        if self.missed >= self.max_missed { Beat::TimedOut(self.missed) } else { Beat::Missed(self.missed) }
+    }
 }
 
 #[allow(dead_code)]
@@ -23,110 +133,405 @@ pub struct EvertextClient {
     write: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
     read: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
     ping_interval: u64,
-    history: String,
+    ping_timeout: u64,
+    settings: SessionSettings,
+    transcript: Option<std::fs::File>,
+    resume: Option<ResumePoint>,
+    continue_policy: Option<ContinuePolicy>,
+    // Codes of the current session; inputs matching them are masked in the transcript.
+    mask: CodeMask,
+    // Consulted for frames the built-in rules leave unanswered. Defaults to
+    // `promptReplies`; replace it here to plug in custom matching logic.
+    prompt_matcher: Option<Box<dyn PromptMatcher + Send + Sync>>,
+    repeat_guard: Option<ContinuePolicy>,
+    last_session: Option<Session>,
+    // Engine.IO session id from the open packet; the server operator can look a session up by it.
+    sid: String,
+    // Client-side number of this connection, counted from 1 per process, so log lines
+    // of one attempt can be told apart from the retries around it.
+    run_id: u64,
+    paused: Arc<AtomicBool>,
+    // Whether the server has confirmed the namespace join; later sessions on the
+    // connection (repeats) don't wait for it again.
+    namespace_joined: bool,
+    // Set from outside to end this session after sending 'exit'.
+    abort: Arc<AtomicBool>,
+    held: Vec<String>,
+    // The last command sent, the trigger that produced it, and when.
+    last_sent: Option<(String, String, Instant)>,
+    output_tx: broadcast::Sender<String>,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, PartialEq)]
-enum GameState {
-    Connected,
-    WaitingForCommandPrompt,
-    SentD,
-    WaitingForRestorePrompt,
-    SentCode,
-    WaitingForServerList,
-    ServerSelected,
-    WaitingProcedure,
-    RapidFire,
-    Finished,
+/// What `connect` does with a parsed open packet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandshakeVerdict {
+    Continue,
+    Retry,
+    Abort,
 }
 
+/// Inspects the open packet JSON before the namespace is joined.
+pub type HandshakeCheck = Box<dyn Fn(&serde_json::Value) -> HandshakeVerdict + Send + Sync>;
+
+/// Check built from the `openPacketAbort` and `openPacketRetry` pointers; abort wins.
+fn open_packet_check(settings: &SessionSettings) -> HandshakeCheck {
+    let retry = settings.open_packet_retry.clone();
+    let abort = settings.open_packet_abort.clone();
+    let flagged = |data: &serde_json::Value, pointers: &[String]| {
+        pointers.iter().any(|p| data.pointer(p).is_some_and(|v| !v.is_null() && *v != serde_json::Value::Bool(false)))
+    };
+    Box::new(move |data| {
+        if flagged(data, &abort) {
+            HandshakeVerdict::Abort
+        } else if flagged(data, &retry) {
+            HandshakeVerdict::Retry
+        } else {
+            HandshakeVerdict::Continue
+        }
+    })
+}
+
+static NEXT_RUN_ID: AtomicU64 = AtomicU64::new(1);
+
 impl EvertextClient {
-    pub async fn connect(cookie: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let mut request = BASE_URL.into_client_request()?;
-        let headers = request.headers_mut();
-        let cookie_header = format!("session={}", cookie);
-        headers.insert("Cookie", HeaderValue::from_str(&cookie_header)?);
-        headers.insert("User-Agent", HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"));
-
-        println!("[INFO] Connecting to EverText WebSocket...");
-        let (mut ws_stream, _) = connect_async(request).await?;
-
-        // 1. Wait for "Open" packet (Type 0) with a timeout
-        let msg = tokio::time::timeout(Duration::from_secs(10), ws_stream.next())
-            .await
-            .map_err(|_| "Connection handshake timed out")?
-            .ok_or("Stream closed")??;
+    /// Connects with `account`'s `cookieCommand` output, or `cookie` if it has none.
+    pub async fn connect_account(account: &Account, cookie: &str, settings: &SessionSettings) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        match &account.cookie_command {
+            Some(command) => {
+                let cookie = run_cookie_command(&account.name, command, settings.timeouts.cookie_command_secs).await?;
+                Self::connect(&cookie, settings).await
+            },
+            None => Self::connect(cookie, settings).await,
+        }
+    }
+
+    /// Connects, judging the open packet by `openPacketRetry` / `openPacketAbort`.
+    pub async fn connect(cookie: &str, settings: &SessionSettings) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Self::connect_with_check(cookie, settings, open_packet_check(settings)).await
+    }
+
+    /// Connects, letting `check` decide from the parsed open packet whether to join,
+    /// reconnect (up to `openPacketRetries` times), or give up with HANDSHAKE_REJECTED.
+    pub async fn connect_with_check(cookie: &str, settings: &SessionSettings, check: HandshakeCheck) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut retries = 0;
+        loop {
+            let (ws_stream, data, started) = Self::open(cookie, settings).await?;
+            match check(&data) {
+                HandshakeVerdict::Continue => return Self::join(ws_stream, data, started, settings).await,
+                HandshakeVerdict::Abort => {
+                    println!("[ERROR] Open packet rejected: {}", data);
+                    return Err(format!("HANDSHAKE_REJECTED: {}", data).into());
+                },
+                HandshakeVerdict::Retry if retries < settings.open_packet_retries => {
+                    retries += 1;
+                    println!("[WARN] Open packet asks to reconnect ({}). Retrying in {}s ({}/{})...", data, settings.timeouts.open_packet_retry_secs, retries, settings.open_packet_retries);
+                    let mut ws_stream = ws_stream;
+                    let _ = ws_stream.close(None).await;
+                    tokio::time::sleep(Duration::from_secs(settings.timeouts.open_packet_retry_secs)).await;
+                },
+                HandshakeVerdict::Retry => {
+                    return Err(format!("HANDSHAKE_REJECTED: still asked to reconnect after {} retries", retries).into());
+                },
+            }
+        }
+    }
+
+    /// Opens the WebSocket and reads the Engine.IO open packet.
+    async fn open(cookie: &str, settings: &SessionSettings) -> Result<(WebSocketStream<MaybeTlsStream<TcpStream>>, serde_json::Value, Instant), Box<dyn std::error::Error + Send + Sync>> {
+        let timeouts = &settings.timeouts;
+        let schedule = match timeouts.handshake_secs.as_slice() {
+            [] => vec![10],
+            list => list.to_vec(),
+        };
+
+        // 1. Wait for "Open" packet (Type 0), reconnecting with the next, longer timeout
+        // in the schedule when a slow server doesn't send it in time.
+        let mut attempt = 0;
+        let (ws_stream, msg, started) = loop {
+            println!("[INFO] Connecting to EverText WebSocket...");
+            let mut ws_stream = open_stream(build_request(cookie)?).await?;
+            let started = Instant::now();
+            match tokio::time::timeout(Duration::from_secs(schedule[attempt]), ws_stream.next()).await {
+                Ok(msg) => break (ws_stream, msg.ok_or("Stream closed")??, started),
+                Err(_) if attempt + 1 < schedule.len() => {
+                    println!("[WARN] No open packet within {}s. Retrying handshake with a {}s timeout ({}/{})...", schedule[attempt], schedule[attempt + 1], attempt + 1, schedule.len() - 1);
+                    attempt += 1;
+                },
+                Err(_) => return Err("Connection handshake timed out".into()),
+            }
+        };
 
         let msg_str = msg.to_string();
+        let json_part = msg_str.strip_prefix('0').ok_or("Failed to handshake")?;
+        let data: serde_json::Value = serde_json::from_str(json_part).map_err(|e| {
+            let raw: String = json_part.chars().take(200).collect();
+            println!("[ERROR] Could not parse open packet ({}): {}", e, raw);
+            format!("HANDSHAKE_PARSE_FAILED: {} (payload: {})", e, raw)
+        })?;
+        Ok((ws_stream, data, started))
+    }
+
+    /// Joins the default namespace on an opened connection.
+    async fn join(mut ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>, data: serde_json::Value, started: Instant, settings: &SessionSettings) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let timeouts = &settings.timeouts;
+        let sid = data["sid"].as_str().ok_or("No SID found")?.to_string();
+        let ping = data["pingInterval"].as_u64().unwrap_or_else(|| {
+            println!("[WARN] Open packet has no pingInterval, assuming {}ms.", timeouts.default_ping_interval_ms);
+            timeouts.default_ping_interval_ms
+        });
+        // Engine.IO drops clients whose ping goes unanswered for pingInterval + pingTimeout.
+        let ping_timeout = data["pingTimeout"].as_u64().unwrap_or_else(|| {
+            println!("[WARN] Open packet has no pingTimeout, assuming {}ms.", timeouts.default_ping_timeout_ms);
+            timeouts.default_ping_timeout_ms
+        });
         
-        if msg_str.starts_with('0') {
-            let json_part = &msg_str[1..];
-            let data: serde_json::Value = serde_json::from_str(json_part)?;
-            
-            let sid = data["sid"].as_str().ok_or("No SID found")?.to_string();
-            let ping = data["pingInterval"].as_u64().unwrap_or(25000);
-            
-            println!("[INFO] Connected! Session ID: {}", sid);
-            
-            // 2. Initial Namespace Request
-            ws_stream.send(Message::Text("40".into())).await?;
-            
-            let (write, read) = ws_stream.split();
-
-            return Ok(Self {
-                write,
-                read,
-                ping_interval: ping,
-                history: String::new(),
-            });
-        }
+        let run_id = NEXT_RUN_ID.fetch_add(1, Ordering::Relaxed);
+        println!("[INFO] Connected! Run {}, Session ID: {} (pingInterval: {}ms, pingTimeout: {}ms, open packet after {}ms)", run_id, sid, ping, ping_timeout, started.elapsed().as_millis());
+        
+        // 2. Initial Namespace Request
+        ws_stream.send(Message::Text("40".into())).await?;
+        
+        let (write, read) = ws_stream.split();
 
-        Err("Failed to handshake".into())
+        Ok(Self {
+            write,
+            read,
+            ping_interval: ping,
+            ping_timeout,
+            settings: settings.clone(),
+            transcript: None,
+            resume: None,
+            continue_policy: None,
+            mask: CodeMask::default(),
+            prompt_matcher: PromptReplies::from_settings(settings).map(|m| Box::new(m) as Box<dyn PromptMatcher + Send + Sync>),
+            repeat_guard: None,
+            last_session: None,
+            sid,
+            run_id,
+            paused: Arc::new(AtomicBool::new(false)),
+            namespace_joined: false,
+            abort: Arc::new(AtomicBool::new(false)),
+            held: Vec::new(),
+            last_sent: None,
+            output_tx: broadcast::channel(256).0,
+        })
     }
 
     pub async fn run_loop(&mut self, account: &Account, decrypted_code: &str, mode: RunMode) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        if decrypted_code.is_empty() {
-             println!("[ERROR] Code is empty/missing for {}", account.name);
-             return Err("MISSING_CODE".into());
+        let decrypted_code = match clean_code(decrypted_code) {
+            Ok(code) => code,
+            Err(reason) => {
+                if reason == "MISSING_CODE" {
+                    println!("[ERROR] Code is empty/missing for {}", account.name);
+                } else {
+                    println!("[ERROR] Code for {} contains control characters. Re-add it.", account.name);
+                }
+                return Err(reason.into());
+            },
+        };
+        let mut session = Session::new(account, decrypted_code, mode.clone(), &self.settings)?;
+        session.set_continue_policy(self.continue_policy.take());
+        self.mask = session.code_mask();
+        session.set_prompt_matcher(self.prompt_matcher.take());
+        session.set_output_sender(self.output_tx.clone());
+        self.transcript = open_transcript(&account.name);
+
+        println!("[INFO][PID:{}] Starting session for account: {} (Mode: {:?}, run: {}, sid: {})", std::process::id(), account.name, mode, self.run_id, self.sid);
+        let mut repeats_left = account.repeat.unwrap_or(0);
+        let result = loop {
+            let result = self.drive(&mut session).await;
+            let complete = matches!(&result, Err(e) if e.to_string() == "SESSION_COMPLETE");
+            if !complete || repeats_left == 0 {
+                break result;
+            }
+            if self.repeat_guard.as_ref().is_some_and(|allowed| !allowed()) {
+                println!("[INFO] Queue stopped. Not repeating {}.", account.name);
+                break result;
+            }
+            repeats_left -= 1;
+            println!("[INFO] {} completed. Repeating ({} repeat(s) left after this)...", account.name, repeats_left);
+            session.repeat();
+            for cmd in session.take_commands() {
+                self.send_command(&cmd).await?;
+            }
+        };
+        let unhandled = session.unhandled_events();
+        if !unhandled.is_empty() {
+            println!("[INFO] Unhandled server events during {}'s session: {}", account.name, unhandled.join(", "));
         }
-        let mut last_ping = Instant::now();
-        let mut state = GameState::Connected;
-        
-        // Trackers
-        let mut auto_sent = false;
-        let mut handout_sent = false;
-        let mut start_sent_at: Option<Instant> = None;
+        self.last_session = Some(session);
+        result.map_err(|e| format!("{} (run {}, sid {})", e, self.run_id, self.sid).into())
+    }
 
-        println!("[INFO][PID:{}] Starting session for account: {} (Mode: {:?})", std::process::id(), account.name, mode);
+    /// Consult `policy` at the "perform more commands" prompt of the next `run_loop`.
+    pub fn set_continue_policy(&mut self, policy: ContinuePolicy) {
+        self.continue_policy = Some(policy);
+    }
+
+    /// Consult `guard` before each of the account's `repeat` runs; false stops repeating.
+    pub fn set_repeat_guard(&mut self, guard: ContinuePolicy) {
+        self.repeat_guard = Some(guard);
+    }
+
+    /// Share a pause switch with the caller. While it is set the connection stays
+    /// alive but commands are held or dropped according to `pausePolicy`.
+    pub fn set_pause_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.paused = flag;
+    }
+
+    /// Share an abort switch with the caller. Once it is set the session sends
+    /// 'exit' and ends with SESSION_ABORTED at the next tick.
+    pub fn set_abort_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.abort = flag;
+    }
+
+    /// Continue the game from `point` on the next `run_loop` instead of sending `start`.
+    pub fn resume_from(&mut self, point: ResumePoint) {
+        self.resume = Some(point);
+    }
+
+    /// Game output lines of every session on this connection, as they arrive.
+    /// A receiver that falls more than 256 lines behind skips ahead.
+    pub fn subscribe_output(&self) -> broadcast::Receiver<String> {
+        self.output_tx.subscribe()
+    }
+
+    /// Engine.IO session id of this connection.
+    pub fn sid(&self) -> &str {
+        &self.sid
+    }
+
+    /// Client-side run number of this connection.
+    pub fn run_id(&self) -> u64 {
+        self.run_id
+    }
+
+    /// Decision trace of the last session (triggers fired, commands sent).
+    pub fn trace(&self) -> Vec<(Instant, String)> {
+        self.last_session.as_ref().map(|s| s.trace()).unwrap_or_default()
+    }
+
+    /// Commands and final screen of the last session, see `Session::command_script`.
+    pub fn command_script(&self) -> String {
+        self.last_session.as_ref().map(|s| s.command_script()).unwrap_or_default()
+    }
+
+    /// Trailing output lines of the last session.
+    pub fn final_screen(&self) -> Vec<String> {
+        self.last_session.as_ref().map(|s| s.final_screen()).unwrap_or_default()
+    }
+
+    /// Items the last session gained, see `rewardPatterns`.
+    pub fn rewards(&self) -> InventoryDelta {
+        self.last_session.as_ref().map(|s| s.rewards()).unwrap_or_default()
+    }
+
+    /// Whether the last session confirmed it did its work.
+    pub fn did_work(&self) -> bool {
+        self.last_session.as_ref().is_some_and(|s| s.did_work())
+    }
+
+    /// Balance the last session read from the output.
+    pub fn balance(&self) -> Option<u64> {
+        self.last_session.as_ref().and_then(|s| s.balance())
+    }
 
-        let mut heartbeat_check = tokio::time::interval(Duration::from_secs(5));
+    /// Server the last session logged into, if it picked one from the list.
+    pub fn selected_server(&self) -> Option<ServerEntry> {
+        self.last_session.as_ref().and_then(|s| s.selected_server())
+    }
+
+    /// Progress of the last session, for resuming it on a new connection.
+    pub fn resume_point(&self) -> Option<ResumePoint> {
+        self.last_session.as_ref().and_then(|s| s.resume_point())
+    }
+
+    async fn drive(&mut self, session: &mut Session) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut heartbeat = Heartbeat::new(self.ping_interval, self.ping_timeout, self.settings.max_missed_heartbeats, Instant::now());
+        let mut start_sent_at: Option<Instant> = None;
+        // The session is started once per connection, however often the namespace is joined.
+        let mut joined = false;
+
+        let timeouts = self.settings.timeouts.clone();
+        let mut heartbeat_check = tokio::time::interval(Duration::from_secs(timeouts.tick_secs.max(1)));
         let mut last_activity = Instant::now(); 
+        let session_started = Instant::now();
+        let mut last_progress = Instant::now();
+        let mut bytes_received: u64 = 0;
+        // Commands waiting for `outputQuietMs` without new output before they are sent.
+        let mut deferred: Vec<(String, String)> = Vec::new();
+        let mut deferred_pace = None;
+        let mut quiet_deadline: Option<tokio::time::Instant> = None;
+        // When the 'start' that follows a retry's 'stop' is due. Waiting here instead of
+        // sleeping keeps pings answered during `stopStartGapMs`.
+        let mut restart_at: Option<tokio::time::Instant> = None;
 
         loop {
             tokio::select! {
+                _ = tokio::time::sleep_until(quiet_deadline.unwrap_or_else(tokio::time::Instant::now)), if quiet_deadline.is_some() => {
+                    quiet_deadline = None;
+                    self.send_batch(std::mem::take(&mut deferred), deferred_pace.take()).await?;
+                }
+                _ = tokio::time::sleep_until(restart_at.unwrap_or_else(tokio::time::Instant::now)), if restart_at.is_some() => {
+                    restart_at = None;
+                    let _ = self.send_frame(format!("42{}", self.settings.start_payload)).await;
+                    start_sent_at = Some(Instant::now());
+                }
                 _ = heartbeat_check.tick() => {
+                     // 0. Pause: a quiet game is expected, so don't count it as stuck.
+                     if self.paused.load(Ordering::Relaxed) {
+                         last_activity = Instant::now();
+                         start_sent_at = start_sent_at.map(|_| Instant::now());
+                     } else if !self.held.is_empty() {
+                         println!("[INFO] Automation resumed. Sending {} held command(s)...", self.held.len());
+                         for cmd in std::mem::take(&mut self.held) {
+                             self.send_command(&cmd).await?;
+                         }
+                     }
+
+                     if self.abort.load(Ordering::Relaxed) {
+                         println!("[WARN] Session aborted. Sending 'exit' and disconnecting...");
+                         let _ = self.send_input("exit").await;
+                         return Err("SESSION_ABORTED".into());
+                     }
+
+                     if !self.namespace_joined && timeouts.namespace_join_secs > 0 && session_started.elapsed().as_secs() >= timeouts.namespace_join_secs {
+                         println!("[ERROR] Server did not confirm the namespace join within {}s.", timeouts.namespace_join_secs);
+                         return Err("NAMESPACE_JOIN_TIMEOUT".into());
+                     }
+
                      // 1. Connection Heartbeat
-                     if last_ping.elapsed().as_millis() as u64 > (self.ping_interval + 15000) {
-                         println!("[ERROR] Connection timed out (no heartbeat from server).");
-                         return Err("CONNECTION_TIMEOUT".into());
+                     match heartbeat.check(Instant::now()) {
+                         Beat::Alive => {},
+                         Beat::Missed(missed) => println!("[WARN] Missed heartbeat from server ({}/{}).", missed, self.settings.max_missed_heartbeats),
+                         Beat::TimedOut(missed) => {
+                             println!("[ERROR] Connection timed out (missed {} heartbeats from server).", missed);
+                             return Err("CONNECTION_TIMEOUT".into());
+                         },
+                     }
+
+                     if timeouts.progress_log_secs > 0 && last_progress.elapsed().as_secs() >= timeouts.progress_log_secs {
+                         println!("[PROGRESS] {} ({}s elapsed)", session.progress(), session_started.elapsed().as_secs());
+                         last_progress = Instant::now();
                      }
 
                      // 2. Game Activity Timeout
-                     if last_activity.elapsed().as_secs() > 180 {
-                         println!("[ERROR] Game Activity timed out (stuck for 180s). Disconnecting...");
+                     if last_activity.elapsed().as_secs() > timeouts.activity_secs {
+                         println!("[ERROR] Game Activity timed out (stuck for {}s). Disconnecting...", timeouts.activity_secs);
                          return Err("ACTIVITY_TIMEOUT".into());
                      }
 
                      // 3. Re-initialization if stuck on black screen
                      if let Some(sent_time) = start_sent_at {
-                         if last_activity.elapsed().as_secs() > 25 && sent_time.elapsed().as_secs() > 25 {
-                             println!("[WARN] Still no activity after 'start'. Retrying initialization with STOP + START sequence...");
-                             let stop_payload = json!(["stop", {"args": ""}]);
-                             let _ = self.write.send(Message::Text(format!("42{}", stop_payload.to_string()).into())).await;
-                             tokio::time::sleep(Duration::from_millis(1500)).await;
-                             let start_payload = json!(["start", {"args": ""}]);
-                             let _ = self.write.send(Message::Text(format!("42{}", start_payload.to_string()).into())).await;
+                         if last_activity.elapsed().as_secs() > timeouts.start_retry_secs && sent_time.elapsed().as_secs() > timeouts.start_retry_secs {
+                             if self.settings.stop_before_start {
+                                 println!("[WARN] Still no activity after 'start'. Retrying initialization with STOP + START sequence...");
+                                 let _ = self.send_frame(format!("42{}", self.settings.stop_payload)).await;
+                                 restart_at = Some(tokio::time::Instant::now() + Duration::from_millis(timeouts.stop_start_gap_ms));
+                             } else {
+                                 println!("[WARN] Still no activity after 'start'. Re-sending START...");
+                                 let _ = self.send_frame(format!("42{}", self.settings.start_payload)).await;
+                             }
                              start_sent_at = Some(Instant::now()); // Reset timer
                          }
                      }
@@ -134,180 +539,379 @@ impl EvertextClient {
                 msg = self.read.next() => {
                     match msg {
                         Some(Ok(m)) => {
-                            let text = m.to_string();
+                            bytes_received += m.len() as u64;
+                            if let Some(limit) = self.settings.max_bytes_per_session.filter(|limit| bytes_received > *limit) {
+                                println!("[ERROR] Received {} bytes this session (limit {}). Disconnecting...", bytes_received, limit);
+                                return Err("BANDWIDTH_LIMIT".into());
+                            }
+                            let text = match m {
+                                Message::Text(text) => text,
+                                Message::Binary(bytes) => {
+                                    println!("[WARN] Received a binary frame ({} bytes). Decoding it as UTF-8.", bytes.len());
+                                    String::from_utf8_lossy(&bytes).into_owned()
+                                },
+                                Message::Ping(_) => {
+                                    // tungstenite queues the pong itself; flushing sends it now.
+                                    self.write.flush().await.map_err(socket_error)?;
+                                    continue;
+                                },
+                                Message::Pong(_) | Message::Frame(_) => continue,
+                                Message::Close(frame) => {
+                                    let reason = frame.map(|f| format!("{} {}", f.code, f.reason)).unwrap_or_else(|| "no reason".to_string());
+                                    println!("[WARN] Server closed the WebSocket ({}).", reason);
+                                    return Err(format!("SOCKET_ERROR: closed by server ({})", reason).into());
+                                },
+                            };
+                            self.record("<<", &text);
                             
                             if text == "2" {
-                                self.write.send(Message::Text("3".into())).await?;
-                                last_ping = Instant::now();
+                                self.send_frame("3".to_string()).await?;
+                                heartbeat.ping(Instant::now());
+                            } else if text == "3" || text.starts_with('5') || text.starts_with('6') {
+                                // Engine.IO pong / upgrade / noop: nothing for a websocket-only client to do.
+                                println!("[DEBUG] Ignoring Engine.IO packet '{}'.", text.chars().take(20).collect::<String>());
+                            } else if text.starts_with('1') {
+                                println!("[WARN] Received Engine.IO close packet.");
+                                return Err("SERVER_DISCONNECT: engine.io close packet".into());
+                            } else if !text.starts_with('4') {
+                                println!("[DEBUG] Unrecognised packet: {}", text.chars().take(100).collect::<String>());
                             } else {
                                 if text.starts_with("40") {
+                                    self.namespace_joined = true;
+                                }
+                                if text.starts_with("40") && joined {
+                                    println!("[WARN] Namespace joined again ({}). Session already started, not restarting it.", text);
+                                } else if text.starts_with("40") && self.resume.is_some() {
+                                    joined = true;
+                                    let point = self.resume.take().unwrap();
+                                    println!("[INFO] Namespace joined. Resuming previous game instead of restarting...");
+                                    session.resume(&point);
+                                    for cmd in session.take_commands() {
+                                        self.send_command(&cmd).await?;
+                                    }
+                                    last_activity = Instant::now();
+                                    // Fall back to STOP + START below if the game stays silent.
+                                    start_sent_at = Some(Instant::now());
+                                } else if text.starts_with("40") {
+                                    joined = true;
                                     println!("[INFO] Namespace joined. Initializing session...");
                                     println!("[ACTION] Sending 'start' event...");
-                                    let start_payload = json!(["start", {"args": ""}]);
-                                    self.write.send(Message::Text(format!("42{}", start_payload.to_string()).into())).await?;
+                                    self.send_frame(format!("42{}", self.settings.start_payload)).await?;
+                                    session.orient();
+                                    for cmd in session.take_commands() {
+                                        println!("[ACTION] Sending '{}' to show the current screen...", cmd);
+                                        self.send_command(&cmd).await?;
+                                    }
                                     last_activity = Instant::now(); 
                                     start_sent_at = Some(Instant::now());
                                 } else if text.starts_with("41") {
                                     println!("[WARN] Received 41 (Session Disconnect). Attempting Re-join...");
-                                    self.write.send(Message::Text("40".into())).await?;
+                                    self.send_frame("40".to_string()).await?;
                                 } else if text.starts_with("42") {
-                                    if text.contains("output") {
+                                    // The server pings during long quiet stages, so a ping counts as activity too.
+                                    if text.contains("output") || text.contains("activity_ping") {
                                         last_activity = Instant::now();
                                     }
-                                    self.handle_event(&text, &mut state, account, decrypted_code, &mut auto_sent, &mut handout_sent, mode).await?;
-                                } else if text.starts_with('4') {
+                                    let result = session.handle_event(&text);
+                                    let pace = session.take_pace();
+                                    let commands = session.take_triggered_commands();
+                                    let quiet = self.settings.output_quiet_ms;
+                                    if quiet > 0 && result.is_ok() {
+                                        // The screen may still be rendering: wait until the output goes quiet.
+                                        let triggered = !commands.is_empty();
+                                        if triggered {
+                                            deferred.extend(commands);
+                                            deferred_pace = deferred_pace.or(pace);
+                                        }
+                                        if !deferred.is_empty() && (triggered || text.contains("output")) {
+                                            quiet_deadline = Some(tokio::time::Instant::now() + Duration::from_millis(quiet));
+                                        }
+                                    } else {
+                                        deferred.extend(commands);
+                                        quiet_deadline = None;
+                                        self.send_batch(std::mem::take(&mut deferred), deferred_pace.take().or(pace)).await?;
+                                    }
+                                    if matches!(&result, Err(e) if e.to_string() == "SESSION_COMPLETE") && self.settings.completion_grace_ms > 0 {
+                                        self.linger(session).await;
+                                    }
+                                    result?;
+                                } else {
                                     println!("[DEBUG] Socket Message: {}", text);
                                 }
                             }
                         }
-                        Some(Err(e)) => return Err(e.into()),
-                        None => return Err("Socket closed".into()),
+                        Some(Err(e)) => return Err(socket_error(e).into()),
+                        None => return Err("SOCKET_ERROR: stream ended".into()),
                     }
                 }
             }
         }
     }
 
+    /// Reads output for `completionGraceMs` after the session finished, feeding it to
+    /// `session` for the final screen. Commands it decides on are discarded.
+    async fn linger(&mut self, session: &mut Session) {
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(self.settings.completion_grace_ms);
+        println!("[INFO] Session complete. Reading output for {}ms before disconnecting...", self.settings.completion_grace_ms);
+        while let Ok(Some(Ok(msg))) = tokio::time::timeout_at(deadline, self.read.next()).await {
+            let Message::Text(text) = msg else { continue };
+            self.record("<<", &text);
+            if text == "2" {
+                let _ = self.send_frame("3".to_string()).await;
+            } else if text.starts_with("42") {
+                let _ = session.handle_event(&text);
+                session.take_commands();
+            }
+        }
+    }
+
+    /// Sends the commands decided on one frame, `pace` apart. A first command
+    /// repeating the one just sent for the same trigger is dropped.
+    async fn send_batch(&mut self, commands: Vec<(String, String)>, pace: Option<Duration>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for (i, (cmd, trigger)) in commands.iter().enumerate() {
+            if let (Some(delay), true) = (pace, i > 0) {
+                tokio::time::sleep(delay).await;
+            }
+            // Repeats within one frame are deliberate (e.g. code chunks).
+            if i == 0 && self.is_duplicate(cmd, trigger) {
+                let shown = if self.mask.hides(cmd) { "<restore code>" } else { cmd.as_str() };
+                println!("[INFO] Dropping '{}': same command was sent moments ago.", shown);
+                continue;
+            }
+            self.send_command(cmd).await?;
+            self.last_sent = Some((cmd.clone(), trigger.clone(), Instant::now()));
+        }
+        Ok(())
+    }
+
+    /// Two prompts answered alike in quick succession (mana, then refill) have
+    /// different triggers, so both answers go out.
+    fn is_duplicate(&self, cmd: &str, trigger: &str) -> bool {
+        let window = Duration::from_millis(self.settings.duplicate_command_window_ms);
+        matches!(&self.last_sent, Some((last, last_trigger, at)) if last == cmd && last_trigger == trigger && at.elapsed() < window)
+    }
+
     async fn send_command(&mut self, cmd: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-         let payload = json!(["input", {"input": cmd}]); 
-         let packet = format!("42{}", payload.to_string());
-         self.write.send(Message::Text(packet.into())).await?;
-         Ok(())
+        self.last_sent = Some((cmd.to_string(), String::new(), Instant::now()));
+        if self.paused.load(Ordering::Relaxed) {
+            match self.settings.pause_policy {
+                PausePolicy::Buffer => {
+                    println!("[INFO] Automation paused. Holding command until resumed.");
+                    self.held.push(cmd.to_string());
+                },
+                PausePolicy::Drop => println!("[INFO] Automation paused. Dropping command."),
+            }
+            return Ok(());
+        }
+        if let Some(steps) = self.settings.macros.get(cmd).cloned() {
+            println!("[ACTION] Expanding macro '{}' into {:?}", cmd, steps);
+            for (i, step) in steps.iter().enumerate() {
+                if i > 0 {
+                    tokio::time::sleep(Duration::from_millis(self.settings.macro_delay_ms)).await;
+                }
+                self.send_input(step).await?;
+            }
+            return Ok(());
+        }
+        self.send_input(cmd).await
     }
 
-    async fn handle_event(&mut self, text: &str, state: &mut GameState, account: &Account, code: &str, auto_sent: &mut bool, handout_sent: &mut bool, mode: RunMode) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let json_part = &text[2..];
-        let event: serde_json::Value = match serde_json::from_str(json_part) {
-            Ok(v) => v,
-            Err(_) => return Ok(()),
-        };
-        
-        if let Some(event_array) = event.as_array() {
-            let event_name = event_array.get(0).and_then(|v| v.as_str()).unwrap_or("");
-            let event_data = event_array.get(1);
-
-            if event_name == "output" {
-                 if let Some(data) = event_data {
-                     if let Some(output_text) = data["data"].as_str() {
-                         let clean_log = output_text.replace("\n", " ");
-                         if !clean_log.trim().is_empty() {
-                             println!("[TERMINAL] {}", clean_log.chars().take(200).collect::<String>());
-                         }
-                         
-                        self.history.push_str(output_text);
-                        if self.history.len() > 15000 {
-                            let mut drain_len = self.history.len() - 15000;
-                            while !self.history.is_char_boundary(drain_len) && drain_len > 0 { drain_len -= 1; }
-                            self.history.replace_range(..drain_len, "");
-                        }
+    async fn send_input(&mut self, cmd: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+         let payload = json!([self.settings.input_event, {"input": cmd}]);
+         if self.mask.hides(cmd) {
+             let shown = json!([self.settings.input_event, {"input": "<restore code>"}]);
+             self.record(">>", &format!("42{}", shown));
+             return self.write_frame(format!("42{}", payload)).await;
+         }
+         self.send_frame(format!("42{}", payload)).await
+    }
 
-                         if self.history.contains("Enter Command to use") {
-                             self.history = self.history.replace("Enter Command to use", "[PROCESSED_PROMPT]");
-                             match mode {
-                                 RunMode::Daily => {
-                                     println!("[ACTION] Sending 'd'...");
-                                     self.send_command("d").await?;
-                                 },
-                                 RunMode::Handout => {
-                                     println!("[ACTION] Sending 'ho'...");
-                                     self.send_command("ho").await?;
-                                 }
-                             }
-                         }
-                         
-                         if self.history.contains("Enter Restore code") {
-                             self.history = self.history.replace("Enter Restore code", "[PROCESSED_CODE]");
-                             println!("[ACTION] Sending Restore Code...");
-                             self.send_command(code).await?;
-                         }
+    async fn send_frame(&mut self, frame: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.record(">>", &frame);
+        self.write_frame(frame).await
+    }
 
-                         if self.history.contains("Which acc u want to Login") {
-                             let target = account.target_server.as_deref().unwrap_or("Default");
-                             if target != "Default" {
-                                 let re = Regex::new(r"(\d+)-->.*?\((.*?)\)").unwrap();
-                                 let mut selected_index = "1".to_string();
-                                 let mut found = false;
-                                 for cap in re.captures_iter(&self.history) {
-                                     if cap[2].contains(target) || (target.to_lowercase() == "all" && cap[2].contains("All of them")) {
-                                         selected_index = cap[1].to_string();
-                                         found = true; break;
-                                     }
-                                 }
-                                 if found {
-                                     println!("[ACTION] Selecting server index: {}", selected_index);
-                                     self.send_command(&selected_index).await?;
-                                     self.history = self.history.replace("Which acc u want to Login", "[PROCESSED_SERVER]");
-                                 }
-                             }
-                         }
+    async fn write_frame(&mut self, frame: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.write.send(Message::Text(frame)).await.map_err(socket_error)?;
+        Ok(())
+    }
 
-                         if self.history.contains("Press y to spend mana on event stages") {
-                             self.history = self.history.replace("Press y to spend mana on event stages", "[PROCESSED_MANA]");
-                             match mode {
-                                 RunMode::Daily => {
-                                     println!("[ACTION] Sending 'y' for mana...");
-                                     self.send_command("y").await?;
-                                 },
-                                 RunMode::Handout => {
-                                     if !*handout_sent {
-                                         self.send_command("ho").await?;
-                                         *handout_sent = true;
-                                     } else {
-                                         self.send_command("y").await?;
-                                     }
-                                 }
-                             }
-                         }
+    fn record(&mut self, direction: &str, frame: &str) {
+        if let Some(file) = self.transcript.as_mut() {
+            let _ = writeln!(file, "{} {}", direction, frame);
+        }
+    }
+}
 
-                         if self.history.contains("next: Go to the next event") {
-                             self.history = self.history.replace("next: Go to the next event", "[PROCESSED_NEXT]");
-                             if !*auto_sent {
-                                 println!("[ACTION] Sending 'auto'...");
-                                 self.send_command("auto").await?;
-                                 *auto_sent = true;
-                             } else {
-                                 println!("[ACTION] Sending 'exit'...");
-                                 self.send_command("exit").await?;
-                             }
-                         }
+/// Runs `command` through the shell and returns its trimmed stdout as the cookie.
+/// Fails with COOKIE_COMMAND_FAILED on a timeout, a non-zero exit or empty output.
+async fn run_cookie_command(account: &str, command: &str, timeout_secs: u64) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    println!("[INFO] Fetching cookie for {} with its cookie command...", account);
+    let started = Instant::now();
+    let child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("COOKIE_COMMAND_FAILED: could not start it: {}", e))?;
+    let output = tokio::time::timeout(Duration::from_secs(timeout_secs), child.wait_with_output()).await
+        .map_err(|_| format!("COOKIE_COMMAND_FAILED: no output after {}s", timeout_secs))?
+        .map_err(|e| format!("COOKIE_COMMAND_FAILED: {}", e))?;
 
-                         if self.history.contains("Press y to perform more commands") {
-                             let h_low = self.history.to_lowercase();
-                             let looks_done = h_low.contains("success") || h_low.contains("finish") || 
-                                              h_low.contains("done") || h_low.contains("already") || 
-                                              *auto_sent || *handout_sent;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for line in stderr.lines().filter(|l| !l.trim().is_empty()) {
+        println!("[DEBUG] cookie command: {}", line);
+    }
+    let last_err = stderr.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("").trim().to_string();
+    if !output.status.success() {
+        return Err(format!("COOKIE_COMMAND_FAILED: exited with {} ({})", output.status, last_err).into());
+    }
+    let cookie = String::from_utf8_lossy(&output.stdout).trim().trim_start_matches("session=").to_string();
+    if cookie.is_empty() {
+        return Err(format!("COOKIE_COMMAND_FAILED: printed no cookie ({})", last_err).into());
+    }
+    println!("[INFO] Cookie command finished in {}ms.", started.elapsed().as_millis());
+    Ok(cookie)
+}
 
-                             if looks_done {
-                                 println!("[INFO] Work confirmed in history. Ending session.");
-                                 return Err("SESSION_COMPLETE".into());
-                             } else {
-                                 println!("[WARN] Exit prompt seen but no work indicators found. Returning to menu...");
-                                 self.history = self.history.replace("Press y to perform more commands", "[PROCESSED_Y]");
-                                 self.send_command("y").await?;
-                             }
-                         }
+/// Labels a tungstenite error as SOCKET_ERROR with its kind. These come from the
+/// network or the WebSocket layer and are worth a reconnect, unlike errors raised
+/// by the session's own prompt handling.
+fn socket_error(e: WsError) -> String {
+    let kind = match &e {
+        WsError::ConnectionClosed | WsError::AlreadyClosed => "closed",
+        WsError::Io(_) => "io",
+        WsError::Tls(_) => "tls",
+        WsError::Protocol(_) => "protocol violation",
+        WsError::Capacity(_) | WsError::WriteBufferFull(_) => "capacity",
+        WsError::Utf8 => "invalid utf-8",
+        _ => "other",
+    };
+    println!("[WARN] WebSocket error ({}): {}", kind, e);
+    format!("SOCKET_ERROR: {}: {}", kind, e)
+}
 
-                         let h_low = self.history.to_lowercase();
-                         if h_low.contains("zigza error") || h_low.contains("incorrect restore code") {
-                             println!("[ERROR] Account Error Detected (Zigza/Code)!");
-                             return Err("ZIGZA_DETECTED".into());
-                         }
-                         if h_low.contains("maximum limit of restore") {
-                             println!("[ERROR] Server Capacity Error!");
-                             return Err("SERVER_FULL".into());
-                         }
-                         if h_low.contains("logged in users") {
-                             println!("[ERROR] Session Expired / Login Required!");
-                             return Err("LOGIN_REQUIRED".into());
-                         }
-                     }
-                 }
-            } else if event_name == "idle_timeout" || event_name == "disconnect" {
-                return Err(format!("SERVER_{}", event_name.to_uppercase()).into());
-            } else if event_name == "activity_ping" || event_name == "user_count_update" {
-                return Ok(());
-            }
+fn build_request(cookie: &str) -> Result<Request, Box<dyn std::error::Error + Send + Sync>> {
+    let mut request = BASE_URL.into_client_request()?;
+    let headers = request.headers_mut();
+    let cookie_header = format!("session={}", cookie);
+    headers.insert("Cookie", HeaderValue::from_str(&cookie_header)?);
+    headers.insert("User-Agent", HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"));
+    Ok(request)
+}
+
+/// Host, port and whether TLS is used for a `ws://` or `wss://` URL.
+fn endpoint(uri: &tokio_tungstenite::tungstenite::http::Uri) -> Result<(String, u16, bool), String> {
+    let host = uri.host().ok_or("No host in URL")?.to_string();
+    let tls = match uri.scheme_str() {
+        Some("wss") => true,
+        Some("ws") => false,
+        other => return Err(format!("CONNECTION_FAILED: unsupported URL scheme {:?}", other.unwrap_or(""))),
+    };
+    Ok((host, uri.port_u16().unwrap_or(if tls { 443 } else { 80 }), tls))
+}
+
+/// Opens the websocket one stage at a time (DNS, TCP, TLS for `wss://`, upgrade),
+/// logging each stage's result and timing so a failed connect says where it failed.
+async fn open_stream(request: Request) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Box<dyn std::error::Error + Send + Sync>> {
+    let (host, port, use_tls) = endpoint(request.uri())?;
+
+    let stage = Instant::now();
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port)).await
+        .map_err(|e| format!("CONNECTION_FAILED: DNS lookup for {}: {}", host, e))?
+        .collect();
+    println!("[INFO] DNS: {} -> {:?} ({}ms)", host, addrs, stage.elapsed().as_millis());
+
+    let stage = Instant::now();
+    let tcp = TcpStream::connect(&addrs[..]).await
+        .map_err(|e| format!("CONNECTION_FAILED: TCP connect to {:?}: {}", addrs, e))?;
+    println!("[INFO] TCP: connected to {} ({}ms)", tcp.peer_addr()?, stage.elapsed().as_millis());
+
+    let stream = if use_tls {
+        let stage = Instant::now();
+        let connector = native_tls::TlsConnector::builder().request_alpns(&["http/1.1"]).build()?;
+        let tls = tokio_native_tls::TlsConnector::from(connector).connect(&host, tcp).await
+            .map_err(|e| format!("CONNECTION_FAILED: TLS handshake with {}: {}", host, e))?;
+        // native-tls has no accessor for the negotiated version or cipher; ALPN and the
+        // certificate's channel-binding hash are what it exposes.
+        let alpn = tls.get_ref().negotiated_alpn().ok().flatten()
+            .map_or_else(|| "none".to_string(), |p| String::from_utf8_lossy(&p).into_owned());
+        let end_point = tls.get_ref().tls_server_end_point().ok().flatten()
+            .map_or_else(|| "unknown".to_string(), |hash| hash.iter().take(8).map(|b| format!("{:02x}", b)).collect());
+        println!("[INFO] TLS: handshake OK (ALPN: {}, certificate hash: {}...) ({}ms)", alpn, end_point, stage.elapsed().as_millis());
+        MaybeTlsStream::NativeTls(tls)
+    } else {
+        println!("[INFO] TLS: skipped, {} is a plain ws:// URL.", host);
+        MaybeTlsStream::Plain(tcp)
+    };
+
+    let stage = Instant::now();
+    let (ws_stream, response) = client_async(request, stream).await
+        .map_err(|e| match &e {
+            WsError::Http(response) if response.status().as_u16() == 429 => format!("RATE_LIMITED: websocket upgrade: {}", e),
+            _ => format!("CONNECTION_FAILED: websocket upgrade: {}", e),
+        })?;
+    println!("[INFO] WS: upgraded with HTTP {} ({}ms)", response.status(), stage.elapsed().as_millis());
+    Ok(ws_stream)
+}
+
+/// Opens `<TRANSCRIPT_DIR>/<account>.log` for appending frames, if transcripts are enabled.
+/// Received frames are written as `<< frame`, sent ones as `>> frame`, and the
+/// file can be fed back through `replay`.
+fn open_transcript(account_name: &str) -> Option<std::fs::File> {
+    let dir = std::env::var("TRANSCRIPT_DIR").ok()?;
+    let _ = std::fs::create_dir_all(&dir);
+    let path = std::path::Path::new(&dir).join(format!("{}.log", account_name));
+    match std::fs::OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            println!("[WARN] Could not open transcript {:?}: {}", path, e);
+            None
         }
-        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL_MS: u64 = 25000;
+    const TIMEOUT_MS: u64 = 20000;
+    const WINDOW: Duration = Duration::from_millis(INTERVAL_MS + TIMEOUT_MS);
+
+    #[test]
+    fn heartbeat_times_out_after_max_missed_windows() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(INTERVAL_MS, TIMEOUT_MS, 3, start);
+        let just_over = WINDOW + Duration::from_millis(1);
+        assert_eq!(heartbeat.check(start + WINDOW), Beat::Alive);
+        assert_eq!(heartbeat.check(start + just_over), Beat::Missed(1));
+        assert_eq!(heartbeat.check(start + just_over + WINDOW), Beat::Alive);
+        assert_eq!(heartbeat.check(start + just_over * 2), Beat::Missed(2));
+        assert_eq!(heartbeat.check(start + just_over * 3), Beat::TimedOut(3));
+    }
+
+    #[test]
+    fn ping_resets_missed_heartbeats() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(INTERVAL_MS, TIMEOUT_MS, 2, start);
+        let just_over = WINDOW + Duration::from_millis(1);
+        assert_eq!(heartbeat.check(start + just_over), Beat::Missed(1));
+        heartbeat.ping(start + just_over + Duration::from_secs(1));
+        assert_eq!(heartbeat.check(start + just_over * 2), Beat::Alive);
+        assert_eq!(heartbeat.check(start + just_over * 2 + Duration::from_secs(1)), Beat::Missed(1));
+        assert_eq!(heartbeat.check(start + just_over * 3 + Duration::from_secs(1)), Beat::TimedOut(2));
+    }
+
+    #[test]
+    fn endpoint_follows_the_url_scheme() {
+        let uri = |url: &str| url.parse::<tokio_tungstenite::tungstenite::http::Uri>().unwrap();
+        assert_eq!(endpoint(&uri("wss://example.com/socket.io/")), Ok(("example.com".to_string(), 443, true)));
+        assert_eq!(endpoint(&uri("ws://localhost/socket.io/")), Ok(("localhost".to_string(), 80, false)));
+        assert_eq!(endpoint(&uri("ws://localhost:3000/")), Ok(("localhost".to_string(), 3000, false)));
+        assert!(endpoint(&uri("http://example.com/")).is_err());
     }
 }
@@ -0,0 +1,291 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::protocol::session::{InventoryDelta, ServerEntry};
+
+/// Summary of one queue run, posted when the queue finishes.
+#[derive(Debug, Serialize)]
+pub struct QueueReport {
+    pub sessions: usize,
+    pub outcomes: BTreeMap<String, usize>,
+    pub failures: Vec<FailedSession>,
+    #[serde(rename = "totalSecs")]
+    pub total_secs: u64,
+    #[serde(rename = "averageSessionSecs")]
+    pub average_session_secs: u64,
+    // Last lines each account's most recent session showed, keyed by account name.
+    #[serde(rename = "finalScreens")]
+    pub final_screens: BTreeMap<String, Vec<String>>,
+    // Server each account last logged into, keyed by account name.
+    pub servers: BTreeMap<String, ServerEntry>,
+    // Last balance each account's session read with `balancePattern`.
+    pub balances: BTreeMap<String, u64>,
+    // Items gained per account, summed over all of its sessions in this run.
+    pub rewards: BTreeMap<String, InventoryDelta>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedSession {
+    pub account: String,
+    pub outcome: String,
+    pub reason: String,
+}
+
+/// One finished session, written as a single JSON line to `resultLog`.
+#[derive(Debug, Serialize)]
+pub struct SessionResult {
+    pub account: String,
+    #[serde(rename = "runId")]
+    pub run_id: u64,
+    pub sid: String,
+    // Error code the session ended with, or "OK".
+    pub outcome: String,
+    #[serde(rename = "didWork")]
+    pub did_work: bool,
+    #[serde(rename = "durationMs")]
+    pub duration_ms: u64,
+    pub server: Option<String>,
+}
+
+impl SessionResult {
+    /// Appends this result to the file at `sink`, or prints it when `sink` is "-".
+    pub fn write_to(&self, sink: &str) -> std::io::Result<()> {
+        let line = serde_json::to_string(self)?;
+        if sink == "-" {
+            println!("{}", line);
+            return Ok(());
+        }
+        use std::io::Write;
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(sink)?;
+        writeln!(file, "{}", line)
+    }
+}
+
+/// Collects per-session outcomes while the queue runs.
+pub struct ReportBuilder {
+    started: Instant,
+    outcomes: BTreeMap<String, usize>,
+    failures: Vec<FailedSession>,
+    session_time: Duration,
+    sessions: usize,
+    final_screens: BTreeMap<String, Vec<String>>,
+    servers: BTreeMap<String, ServerEntry>,
+    balances: BTreeMap<String, u64>,
+    rewards: BTreeMap<String, InventoryDelta>,
+}
+
+impl ReportBuilder {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            outcomes: BTreeMap::new(),
+            failures: Vec::new(),
+            session_time: Duration::ZERO,
+            sessions: 0,
+            final_screens: BTreeMap::new(),
+            servers: BTreeMap::new(),
+            balances: BTreeMap::new(),
+            rewards: BTreeMap::new(),
+        }
+    }
+
+    pub fn success(&mut self, outcome: &str, duration: Duration) {
+        self.count(outcome, duration);
+    }
+
+    pub fn failure(&mut self, account: &str, outcome: &str, reason: &str, duration: Duration) {
+        self.count(outcome, duration);
+        self.failures.push(FailedSession {
+            account: account.to_string(),
+            outcome: outcome.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    pub fn final_screen(&mut self, account: &str, screen: Vec<String>) {
+        self.final_screens.insert(account.to_string(), screen);
+    }
+
+    pub fn server(&mut self, account: &str, server: ServerEntry) {
+        self.servers.insert(account.to_string(), server);
+    }
+
+    pub fn balance(&mut self, account: &str, balance: u64) {
+        self.balances.insert(account.to_string(), balance);
+    }
+
+    pub fn rewards(&mut self, account: &str, rewards: &InventoryDelta) {
+        self.rewards.entry(account.to_string()).or_default().merge(rewards);
+    }
+
+    fn count(&mut self, outcome: &str, duration: Duration) {
+        *self.outcomes.entry(outcome.to_string()).or_insert(0) += 1;
+        self.session_time += duration;
+        self.sessions += 1;
+    }
+
+    pub fn finish(self) -> QueueReport {
+        let average = if self.sessions == 0 { 0 } else { self.session_time.as_secs() / self.sessions as u64 };
+        QueueReport {
+            sessions: self.sessions,
+            outcomes: self.outcomes,
+            failures: self.failures,
+            total_secs: self.started.elapsed().as_secs(),
+            average_session_secs: average,
+            final_screens: self.final_screens,
+            servers: self.servers,
+            balances: self.balances,
+            rewards: self.rewards,
+        }
+    }
+}
+
+impl QueueReport {
+    /// Discord-formatted summary.
+    pub fn summary(&self) -> String {
+        let mut text = format!(
+            "📊 **Queue Report** • {} session(s) in {} • avg {}\n",
+            self.sessions, format_secs(self.total_secs), format_secs(self.average_session_secs)
+        );
+        for (outcome, count) in &self.outcomes {
+            text.push_str(&format!("- {}: **{}**\n", outcome, count));
+        }
+        if !self.servers.is_empty() {
+            let mut per_server: BTreeMap<&str, usize> = BTreeMap::new();
+            for server in self.servers.values() {
+                *per_server.entry(server.name.as_str()).or_insert(0) += 1;
+            }
+            let list: Vec<String> = per_server.iter().map(|(name, count)| format!("{} ×{}", name, count)).collect();
+            text.push_str(&format!("Servers: {}\n", list.join(", ")));
+        }
+        let gained = self.total_rewards();
+        if !gained.is_empty() {
+            text.push_str(&format!("Gained: {}\n", gained.describe()));
+        }
+        if !self.failures.is_empty() {
+            text.push_str("\n**Failures:**\n");
+            for failure in &self.failures {
+                text.push_str(&format!("- **{}** ({}): {}\n", failure.account, failure.outcome, failure.reason));
+            }
+        }
+        if text.len() > 1900 {
+            let mut cut = 1900;
+            while !text.is_char_boundary(cut) { cut -= 1; }
+            text.truncate(cut);
+            text.push_str("\n... (truncated)");
+        }
+        text
+    }
+
+    /// Items gained across all accounts.
+    pub fn total_rewards(&self) -> InventoryDelta {
+        let mut total = InventoryDelta::default();
+        for rewards in self.rewards.values() {
+            total.merge(rewards);
+        }
+        total
+    }
+}
+
+/// Formats a `QueueReport` as a Discord webhook payload with one embed.
+pub struct DiscordSummaryReporter {
+    // Adds a field listing each account's server and balance.
+    pub include_accounts: bool,
+}
+
+impl DiscordSummaryReporter {
+    pub fn payload(&self, report: &QueueReport) -> serde_json::Value {
+        let color = if report.failures.is_empty() { 0x2ecc71 } else { 0xe67e22 };
+        let outcomes: Vec<String> = report.outcomes.iter().map(|(outcome, count)| format!("{}: **{}**", outcome, count)).collect();
+        let mut fields = vec![serde_json::json!({"name": "Outcomes", "value": field_value(&outcomes), "inline": true})];
+        if !report.balances.is_empty() {
+            let total: u64 = report.balances.values().sum();
+            fields.push(serde_json::json!({"name": "Mana (last read)", "value": format!("{} across {} account(s)", total, report.balances.len()), "inline": true}));
+        }
+        let gained = report.total_rewards();
+        if !gained.is_empty() {
+            fields.push(serde_json::json!({"name": "Gained", "value": field_value(&[gained.describe()]), "inline": true}));
+        }
+        if !report.failures.is_empty() {
+            let failures: Vec<String> = report.failures.iter()
+                .map(|f| format!("**{}** ({}): {}", f.account, f.outcome, f.reason))
+                .collect();
+            fields.push(serde_json::json!({"name": format!("Failures ({})", report.failures.len()), "value": field_value(&failures)}));
+        }
+        if self.include_accounts {
+            let mut names: Vec<&String> = report.servers.keys().chain(report.balances.keys()).collect();
+            names.sort();
+            names.dedup();
+            let accounts: Vec<String> = names.into_iter().map(|name| {
+                let server = report.servers.get(name).map_or("-", |s| s.name.as_str());
+                let balance = report.balances.get(name).map_or("-".to_string(), u64::to_string);
+                format!("{}: {} • {}", name, server, balance)
+            }).collect();
+            if !accounts.is_empty() {
+                fields.push(serde_json::json!({"name": "Accounts (server • mana)", "value": field_value(&accounts)}));
+            }
+        }
+        serde_json::json!({
+            "embeds": [{
+                "title": "📊 Queue Report",
+                "description": format!("{} session(s) in {} • avg {}", report.sessions, format_secs(report.total_secs), format_secs(report.average_session_secs)),
+                "color": color,
+                "fields": fields,
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+            }]
+        })
+    }
+}
+
+/// Joins lines into an embed field value, cut to Discord's 1024-character limit.
+fn field_value(lines: &[String]) -> String {
+    let mut value = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        let more = format!("… and {} more", lines.len() - i);
+        if value.len() + 1 + line.len() + 1 + more.len() > 1024 {
+            if !value.is_empty() {
+                value.push('\n');
+            }
+            value.push_str(&more);
+            break;
+        }
+        if !value.is_empty() {
+            value.push('\n');
+        }
+        value.push_str(line);
+    }
+    if value.is_empty() { "-".to_string() } else { value }
+}
+
+fn format_secs(secs: u64) -> String {
+    format!("{}m{:02}s", secs / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_value_short_list() {
+        assert_eq!(field_value(&[]), "-");
+        assert_eq!(field_value(&["a".to_string(), "b".to_string()]), "a\nb");
+    }
+
+    #[test]
+    fn field_value_truncates_on_its_own_line() {
+        let lines: Vec<String> = (0..100).map(|i| format!("{:02} {}", i, "x".repeat(40))).collect();
+        let value = field_value(&lines);
+        assert!(value.len() <= 1024);
+        let shown: Vec<&str> = value.lines().collect();
+        let (last, kept) = shown.split_last().unwrap();
+        assert_eq!(*last, format!("… and {} more", lines.len() - kept.len()));
+        assert_eq!(kept, &lines[..kept.len()]);
+    }
+
+    #[test]
+    fn field_value_first_line_too_long() {
+        let value = field_value(&["x".repeat(2000)]);
+        assert_eq!(value, "… and 1 more");
+    }
+}